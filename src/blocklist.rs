@@ -0,0 +1,105 @@
+//! Blocked and muted peers and rooms
+//!
+//! Muting only hides: a muted peer's room messages (or everything in a
+//! muted room) is not shown, and DMs from muted peers do not trigger
+//! notifications. Blocking a peer additionally rejects their DMs and drops
+//! their contact requests without showing them. Both lists are persisted
+//! per profile.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Lists {
+    #[serde(default)]
+    blocked: BTreeSet<String>,
+    #[serde(default)]
+    muted_peers: BTreeSet<String>,
+    #[serde(default)]
+    muted_rooms: BTreeSet<String>,
+}
+
+/// Block and mute lists, persisted as TOML.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    path: PathBuf,
+    lists: Lists,
+}
+
+impl Blocklist {
+    pub fn load(path: &Path) -> Result<Self> {
+        let lists = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid blocklist {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Lists::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lists,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let text = toml::to_string_pretty(&self.lists).context("Failed to encode blocklist")?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn is_blocked(&self, identity: &str) -> bool {
+        self.lists.blocked.contains(identity)
+    }
+
+    /// Whether `identity`'s room messages are hidden (muted or blocked).
+    pub fn hides_peer(&self, identity: &str) -> bool {
+        self.is_blocked(identity) || self.lists.muted_peers.contains(identity)
+    }
+
+    pub fn is_room_muted(&self, room: &str) -> bool {
+        self.lists.muted_rooms.contains(room)
+    }
+
+    /// Each of these returns `false` when nothing changed.
+    pub fn block(&mut self, identity: &str) -> bool {
+        self.lists.blocked.insert(identity.to_string())
+    }
+
+    pub fn unblock(&mut self, identity: &str) -> bool {
+        self.lists.blocked.remove(identity)
+    }
+
+    pub fn mute_peer(&mut self, identity: &str) -> bool {
+        self.lists.muted_peers.insert(identity.to_string())
+    }
+
+    pub fn unmute_peer(&mut self, identity: &str) -> bool {
+        self.lists.muted_peers.remove(identity)
+    }
+
+    pub fn mute_room(&mut self, room: &str) -> bool {
+        self.lists.muted_rooms.insert(room.to_string())
+    }
+
+    pub fn unmute_room(&mut self, room: &str) -> bool {
+        self.lists.muted_rooms.remove(room)
+    }
+
+    pub fn blocked(&self) -> impl Iterator<Item = &String> {
+        self.lists.blocked.iter()
+    }
+
+    pub fn muted_peers(&self) -> impl Iterator<Item = &String> {
+        self.lists.muted_peers.iter()
+    }
+
+    pub fn muted_rooms(&self) -> impl Iterator<Item = &String> {
+        self.lists.muted_rooms.iter()
+    }
+}
@@ -0,0 +1,314 @@
+//! Bootstrap peers, retry and automatic rejoin
+//!
+//! Peers we were connected to are saved on shutdown and tried (after any
+//! explicitly configured peers) on the next start, so a node does not need
+//! a fresh bootstrap string every launch. Candidates are joined
+//! concurrently so one dead seed cannot stall or isolate the node. A
+//! watchdog re-bootstraps when the node loses all of its connections.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use korium::Node;
+
+use crate::{parse_bootstrap, ui};
+
+/// Maximum number of peers kept in the known-peers file.
+pub const MAX_KNOWN_PEERS: usize = 32;
+/// Rounds over the candidate list before giving up.
+pub const JOIN_ATTEMPTS: u32 = 3;
+/// Delay before the second round; doubles each round.
+pub const JOIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Per-target limit for a single join attempt.
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Limit for bootstrapping from the public network.
+pub const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the watchdog checks for connected peers.
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Outcome of joining one bootstrap target.
+#[derive(Debug)]
+pub struct JoinReport {
+    pub target: String,
+    /// Time until the join completed, or why it failed
+    pub result: std::result::Result<Duration, String>,
+}
+
+impl std::fmt::Display for JoinReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (identity, addr) =
+            parse_bootstrap(&self.target).unwrap_or_else(|_| (String::new(), self.target.clone()));
+        let short = &identity[..8.min(identity.len())];
+        match &self.result {
+            Ok(rtt) => write!(
+                f,
+                "  {} {}  {}  ({:.0}ms)",
+                ui::paint(ui::GREEN, "✓"),
+                addr,
+                short,
+                rtt.as_secs_f64() * 1000.0
+            ),
+            Err(e) => write!(
+                f,
+                "  {} {}  {}  {}",
+                ui::paint(ui::RED, "✗"),
+                addr,
+                short,
+                e
+            ),
+        }
+    }
+}
+
+/// Parse a blob of bootstrap lines: one `<address>/<identity_hex>` per
+/// line, blank lines and `#` comments ignored.
+pub fn parse_bootstrap_list(text: &str) -> Result<Vec<String>> {
+    let mut targets = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        parse_bootstrap(line).with_context(|| format!("line {}: {line}", n + 1))?;
+        targets.push(line.to_string());
+    }
+    Ok(targets)
+}
+
+/// Expand `--join` values into bootstrap strings.
+///
+/// A value is a single bootstrap string, a multi-line blob of them, or
+/// `@<file>` (`@-` for stdin) naming a file in the same format.
+pub fn expand_join_targets(values: &[String]) -> Result<Vec<String>> {
+    let mut targets = Vec::new();
+    for value in values {
+        let expanded = match value.strip_prefix('@') {
+            Some("-") => {
+                let mut text = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)
+                    .context("Failed to read bootstrap list from stdin")?;
+                parse_bootstrap_list(&text).context("Invalid bootstrap list on stdin")?
+            }
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read bootstrap file {path}"))?;
+                parse_bootstrap_file(path, &text)?
+            }
+            None => parse_bootstrap_list(value)?,
+        };
+        add_targets(&mut targets, expanded);
+    }
+    Ok(targets)
+}
+
+/// Expand `/join-peer` arguments typed in the chat. Files are read without
+/// blocking the runtime, and `@-` is refused because stdin is the chat's
+/// own input.
+pub async fn expand_runtime_join_targets(values: &[String]) -> Result<Vec<String>> {
+    let mut targets = Vec::new();
+    for value in values {
+        let expanded = match value.strip_prefix('@') {
+            Some("-") => anyhow::bail!("@- only works with --join; stdin is the chat input"),
+            Some(path) => {
+                let text = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Failed to read bootstrap file {path}"))?;
+                parse_bootstrap_file(path, &text)?
+            }
+            None => parse_bootstrap_list(value)?,
+        };
+        add_targets(&mut targets, expanded);
+    }
+    Ok(targets)
+}
+
+fn parse_bootstrap_file(path: &str, text: &str) -> Result<Vec<String>> {
+    parse_bootstrap_list(text).with_context(|| format!("Invalid bootstrap file {path}"))
+}
+
+/// Append `expanded` to `targets`, skipping duplicates.
+fn add_targets(targets: &mut Vec<String>, expanded: Vec<String>) {
+    for target in expanded {
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+}
+
+/// Bootstrap from the public Korium network, giving up after
+/// [`BOOTSTRAP_TIMEOUT`].
+pub async fn bootstrap_public(node: &Node) -> Result<()> {
+    match tokio::time::timeout(BOOTSTRAP_TIMEOUT, node.bootstrap()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => anyhow::bail!("Bootstrap failed: {e}"),
+        Err(_) => anyhow::bail!(
+            "Bootstrap failed: no answer within {}s",
+            BOOTSTRAP_TIMEOUT.as_secs()
+        ),
+    }
+}
+
+/// Join every target concurrently and report each outcome.
+pub async fn join_all(node: &Node, targets: &[String]) -> Vec<JoinReport> {
+    let attempts = targets.iter().map(|target| async move {
+        let started = Instant::now();
+        let result = match parse_bootstrap(target) {
+            Ok((peer_identity, addr)) => {
+                match tokio::time::timeout(JOIN_TIMEOUT, node.join(&peer_identity, &[addr])).await {
+                    Ok(Ok(())) => Ok(started.elapsed()),
+                    Ok(Err(e)) => Err(e.to_string()),
+                    Err(_) => Err("timeout".to_string()),
+                }
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        JoinReport {
+            target: target.clone(),
+            result,
+        }
+    });
+    futures::future::join_all(attempts).await
+}
+
+/// Read saved bootstrap strings, skipping blank and `#` comment lines.
+/// A missing file yields an empty list.
+pub fn load_known_peers(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .map(|text| {
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .filter(|l| parse_bootstrap(l).is_ok())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Save the peers we currently know about in front of the previously
+/// saved ones, dropping duplicate identities.
+pub async fn save_known_peers(node: &Node, path: &Path) -> Result<usize> {
+    let mut peers: Vec<String> = node
+        .all_contacts()
+        .await
+        .iter()
+        .filter_map(|c| {
+            let addr = c.addrs.first()?;
+            Some(format!("{}/{}", addr, hex::encode(c.identity.as_bytes())))
+        })
+        .collect();
+    peers.extend(load_known_peers(path));
+
+    let mut seen = std::collections::HashSet::new();
+    peers.retain(|p| match parse_bootstrap(p) {
+        Ok((identity, _)) => seen.insert(identity),
+        Err(_) => false,
+    });
+    peers.truncate(MAX_KNOWN_PEERS);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut text = String::from("# six7 known bootstrap peers (most recent first)\n");
+    for p in &peers {
+        text.push_str(p);
+        text.push('\n');
+    }
+    std::fs::write(path, text)
+        .with_context(|| format!("Failed to write known peers {}", path.display()))?;
+    Ok(peers.len())
+}
+
+/// Join all candidates concurrently, then try the public network when
+/// `public` is set, repeating with exponential backoff until at least one
+/// succeeds. `report` sees every per-target outcome of every round.
+/// Returns `Ok(false)` when there is nothing to try.
+pub async fn join_any(
+    node: &Node,
+    candidates: &[String],
+    public: bool,
+    report: impl Fn(&JoinReport),
+) -> Result<bool> {
+    if candidates.is_empty() && !public {
+        return Ok(false);
+    }
+
+    let mut backoff = JOIN_BACKOFF;
+    let mut last_err = None;
+    for attempt in 1..=JOIN_ATTEMPTS {
+        let reports = join_all(node, candidates).await;
+        reports.iter().for_each(&report);
+        if reports.iter().any(|r| r.result.is_ok()) {
+            return Ok(true);
+        }
+        if let Some(failed) = reports.last() {
+            let reason = failed.result.as_ref().err().cloned().unwrap_or_default();
+            last_err = Some(anyhow::anyhow!(
+                "Join failed: no bootstrap peer reachable (last: {reason})"
+            ));
+        }
+        if public {
+            match bootstrap_public(node).await {
+                Ok(()) => return Ok(true),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        if attempt < JOIN_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    Err(last_err.expect("at least one bootstrap attempt was made"))
+}
+
+/// Re-bootstrap whenever the node has no connected peers.
+///
+/// Candidates are re-read from the known-peers file on each attempt and
+/// preceded by `configured`, so peers learned since startup are used too.
+pub fn spawn_watchdog(
+    node: Arc<Node>,
+    configured: Vec<String>,
+    known_peers_file: std::path::PathBuf,
+    public: bool,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+        interval.tick().await;
+        let mut isolated = false;
+        loop {
+            interval.tick().await;
+            if !node.connected_contacts().await.is_empty() {
+                isolated = false;
+                continue;
+            }
+
+            let mut candidates = configured.clone();
+            candidates.extend(load_known_peers(&known_peers_file));
+            if candidates.is_empty() && !public {
+                continue;
+            }
+            if !isolated {
+                println!(
+                    "{} No connected peers, re-bootstrapping...",
+                    ui::paint(ui::YELLOW, "[network]")
+                );
+                isolated = true;
+            }
+            match join_any(&node, &candidates, public, |r| {
+                tracing::debug!("rejoin {r}")
+            })
+            .await
+            {
+                Ok(_) => {
+                    println!("{} Rejoined the network", ui::paint(ui::GREEN, "[network]"));
+                    isolated = false;
+                }
+                Err(e) => tracing::debug!("watchdog rejoin failed: {e}"),
+            }
+        }
+    });
+}
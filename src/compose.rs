@@ -0,0 +1,139 @@
+//! Multiline composition
+//!
+//! `/paste` collects the lines that follow, untouched, until a line with
+//! only [`TERMINATOR`] on it, and sends them as one message. Drafts too
+//! large for a message can be sent as a document attachment instead (see
+//! [`crate::document`]) after the user confirms.
+
+use crate::document::MAX_DOCUMENT_BYTES;
+use crate::MAX_MESSAGE_SIZE_BYTES;
+
+/// Line that ends a paste.
+pub const TERMINATOR: &str = ".";
+/// Line that throws a paste away.
+pub const CANCEL: &str = "/cancel";
+/// Bytes of a message left for its envelope: ids, timestamps, signature.
+pub const ENVELOPE_BYTES: usize = 1024;
+
+/// Most content bytes that fit in one message once it is wrapped in its
+/// envelope and, when `sealed` with a group key, hex-encoded.
+pub fn content_budget(sealed: bool) -> usize {
+    let budget = MAX_MESSAGE_SIZE_BYTES - ENVELOPE_BYTES;
+    if sealed {
+        budget / 2
+    } else {
+        budget
+    }
+}
+
+/// What to do after feeding a line to a [`Draft`].
+#[derive(Debug)]
+pub enum Outcome {
+    /// Keep collecting lines
+    Pending,
+    Cancelled,
+    /// Send this text as one message
+    Message(String),
+    /// The draft has this many bytes, too many for a message; ask whether
+    /// to send it as a document and feed the answer
+    TooLarge(usize),
+    /// Send this text as a document
+    Document(String),
+    /// The draft grew past what even a document may hold
+    Overflow,
+}
+
+/// A multiline message being composed, addressed to a DM peer or (when
+/// `peer` is `None`) the active room.
+#[derive(Debug)]
+pub struct Draft {
+    pub peer: Option<String>,
+    /// Content bytes one message holds, see [`content_budget`]
+    pub budget: usize,
+    lines: Vec<String>,
+    bytes: usize,
+    /// Text waiting for the answer to the document question
+    confirming: Option<String>,
+}
+
+impl Draft {
+    pub fn new(peer: Option<String>, sealed: bool) -> Self {
+        Self {
+            peer,
+            budget: content_budget(sealed),
+            lines: Vec::new(),
+            bytes: 0,
+            confirming: None,
+        }
+    }
+
+    pub fn feed(&mut self, line: &str) -> Outcome {
+        if let Some(text) = self.confirming.take() {
+            return match line.trim().to_lowercase().as_str() {
+                "y" | "yes" => Outcome::Document(text),
+                _ => Outcome::Cancelled,
+            };
+        }
+        match line.trim_end() {
+            TERMINATOR => {}
+            CANCEL => return Outcome::Cancelled,
+            _ => {
+                self.bytes += line.len() + 1;
+                if self.bytes > MAX_DOCUMENT_BYTES {
+                    return Outcome::Overflow;
+                }
+                self.lines.push(line.trim_end_matches('\r').to_string());
+                return Outcome::Pending;
+            }
+        }
+        let text = std::mem::take(&mut self.lines).join("\n");
+        if text.trim().is_empty() {
+            Outcome::Cancelled
+        } else if text.len() > self.budget {
+            let size = text.len();
+            self.confirming = Some(text);
+            Outcome::TooLarge(size)
+        } else {
+            Outcome::Message(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish(draft: &mut Draft, text: &str) -> Outcome {
+        assert!(matches!(draft.feed(text), Outcome::Pending));
+        draft.feed(TERMINATOR)
+    }
+
+    #[test]
+    fn sealed_drafts_leave_room_for_encryption() {
+        let fits = "a".repeat(content_budget(true));
+        let cases = [
+            (false, fits.clone(), true),
+            (true, fits.clone(), true),
+            (true, format!("{fits}a"), false),
+            (false, format!("{fits}a"), true),
+            (false, "a".repeat(content_budget(false) + 1), false),
+        ];
+        for (sealed, text, message) in cases {
+            let outcome = finish(&mut Draft::new(None, sealed), &text);
+            assert_eq!(
+                matches!(outcome, Outcome::Message(_)),
+                message,
+                "{} bytes, sealed: {sealed}: {outcome:?}",
+                text.len()
+            );
+        }
+    }
+
+    #[test]
+    fn large_drafts_can_become_documents() {
+        let mut draft = Draft::new(Some("peer".to_string()), false);
+        let text = "a".repeat(MAX_MESSAGE_SIZE_BYTES);
+        assert!(matches!(finish(&mut draft, &text), Outcome::TooLarge(_)));
+        assert!(matches!(draft.feed("y"), Outcome::Document(t) if t == text));
+    }
+}
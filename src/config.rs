@@ -0,0 +1,461 @@
+//! Configuration file and named profiles
+//!
+//! Settings are resolved in three layers: built-in defaults, the selected
+//! profile from the TOML config file, then command-line flags.
+//!
+//! ```toml
+//! default_profile = "work"
+//!
+//! [profiles.work]
+//! name = "Alice"
+//! rooms = ["dev", "ops"]
+//! bootstrap_peers = ["192.168.1.100:4433/abc123...", "@/etc/six7/seeds.txt"]
+//! identity_file = "~/.config/six7/work.key"
+//! known_peers_file = "~/.local/share/six7/work-peers.txt"
+//! output = "color"
+//! blocked_response = "ignore"
+//! rich_text = false
+//! presence = true
+//! metrics_addr = "127.0.0.1:9187"
+//!
+//! [profiles.work.notify]
+//! bell = true
+//! mention = false
+//! command = "notify-send six7 \"$SIX7_FROM: $SIX7_TEXT\""
+//! ```
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{bootstrap, invite, validate_room, Args, Command};
+
+pub const DEFAULT_NAME: &str = "anon";
+pub const DEFAULT_ROOM: &str = "lobby";
+pub const CONFIG_ENV: &str = "SIX7_CONFIG";
+
+/// Terminal output style.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// ANSI colors and styles
+    #[default]
+    Color,
+    /// No escape sequences (for logs and dumb terminals)
+    Plain,
+}
+
+/// How to answer DMs from blocked peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockedResponse {
+    /// Reply `AckResponse { ack: false }`, so the sender sees the DM was refused
+    #[default]
+    Nack,
+    /// Send no response at all, so the sender cannot tell a block from an outage
+    Ignore,
+}
+
+/// Local notification hooks for incoming DMs and mentions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifySettings {
+    /// Ring the terminal bell
+    #[serde(default)]
+    pub bell: bool,
+    /// Shell command to run; receives `SIX7_KIND`, `SIX7_FROM` and `SIX7_TEXT`
+    pub command: Option<String>,
+    /// Notify for incoming DMs
+    #[serde(default = "enabled")]
+    pub dm: bool,
+    /// Notify when a room message mentions us
+    #[serde(default = "enabled")]
+    pub mention: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for NotifySettings {
+    fn default() -> Self {
+        Self {
+            bell: false,
+            command: None,
+            dm: true,
+            mention: true,
+        }
+    }
+}
+
+/// One named profile. Every field is optional so profiles only need to
+/// state what differs from the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    /// Bootstrap from the public Korium network
+    pub public_bootstrap: Option<bool>,
+    /// Where peers learned at shutdown are saved for the next start
+    /// [default: `known_peers.txt` in the state directory]
+    pub known_peers_file: Option<PathBuf>,
+    /// Directory for other persisted state (rooms, contacts, ...)
+    pub state_dir: Option<PathBuf>,
+    pub identity_file: Option<PathBuf>,
+    pub output: Option<OutputMode>,
+    pub debug: Option<bool>,
+    pub notify: Option<NotifySettings>,
+    /// Answer to DMs from blocked peers
+    pub blocked_response: Option<BlockedResponse>,
+    /// Render markdown-style emphasis, code and links in messages
+    pub rich_text: Option<bool>,
+    /// Share online/away status and typing with peers that support it
+    pub presence: Option<bool>,
+    /// Serve Prometheus metrics over HTTP at this address
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// Contents of the config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Profile used when `--profile` is not given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Effective settings after merging defaults, profile and CLI flags.
+#[derive(Debug, Clone, Serialize)]
+pub struct Settings {
+    /// Config file the settings were loaded from
+    #[serde(skip)]
+    pub config_path: PathBuf,
+    /// Profile the settings came from, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub name: String,
+    /// Rooms to join; the first one is active at startup
+    pub rooms: Vec<String>,
+    pub port: u16,
+    pub bootstrap_peers: Vec<String>,
+    pub public_bootstrap: bool,
+    pub known_peers_file: PathBuf,
+    pub state_dir: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<PathBuf>,
+    pub output: OutputMode,
+    pub debug: bool,
+    pub notify: NotifySettings,
+    pub blocked_response: BlockedResponse,
+    pub rich_text: bool,
+    pub presence: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl Settings {
+    /// Load the config file and merge it with the command-line flags.
+    pub fn resolve(args: &Args) -> Result<Self> {
+        let (path, explicit) = match &args.config {
+            Some(p) => (p.clone(), true),
+            None => match std::env::var_os(CONFIG_ENV) {
+                Some(p) => (PathBuf::from(p), true),
+                None => (default_config_path(), false),
+            },
+        };
+        let file = ConfigFile::load(&path, explicit)?;
+        Self::merge(path, &file, args)
+    }
+
+    fn merge(config_path: PathBuf, file: &ConfigFile, args: &Args) -> Result<Self> {
+        let profile_name = args
+            .profile
+            .clone()
+            .or_else(|| file.default_profile.clone());
+        let profile = match &profile_name {
+            Some(name) => file
+                .profiles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("Unknown profile: {name}"))?,
+            None => Profile::default(),
+        };
+
+        // `config show` prints join targets as configured: expanding them
+        // would read bootstrap files, or block on stdin for `@-`.
+        let expand = !matches!(args.command, Some(Command::Config { .. }));
+        let bootstrap_peers = match (args.join.is_empty(), expand) {
+            (true, true) => bootstrap::expand_join_targets(&profile.bootstrap_peers)
+                .context("Invalid bootstrap_peers in profile")?,
+            (false, true) => bootstrap::expand_join_targets(&args.join)?,
+            (true, false) => profile.bootstrap_peers,
+            (false, false) => args.join.clone(),
+        };
+
+        // Rooms named by invite codes on the command line are joined too,
+        // and become active unless --room says otherwise.
+        let mut rooms = profile.rooms;
+        for target in args.join.iter().filter(|t| invite::is_invite_code(t)) {
+            if let Some(room) = invite::Invite::decode(target)?.room {
+                rooms.retain(|r| *r != room);
+                rooms.insert(0, room);
+            }
+        }
+        if let Some(ref room) = args.room {
+            rooms.retain(|r| r != room);
+            rooms.insert(0, room.clone());
+        }
+        if rooms.is_empty() {
+            rooms.push(DEFAULT_ROOM.to_string());
+        }
+        for room in &rooms {
+            validate_room(room).with_context(|| format!("Invalid room {room:?}"))?;
+        }
+
+        let state_dir = profile
+            .state_dir
+            .map(|p| expand_home(&p))
+            .unwrap_or_else(|| match profile_name {
+                Some(ref name) => data_dir().join("profiles").join(name),
+                None => data_dir(),
+            });
+        let known_peers_file = profile
+            .known_peers_file
+            .map(|p| expand_home(&p))
+            .unwrap_or_else(|| state_dir.join("known_peers.txt"));
+
+        Ok(Self {
+            config_path,
+            profile: profile_name,
+            name: args
+                .name
+                .clone()
+                .or(profile.name)
+                .unwrap_or_else(|| DEFAULT_NAME.to_string()),
+            rooms,
+            port: args.port.or(profile.port).unwrap_or(0),
+            bootstrap_peers,
+            public_bootstrap: args.bootstrap || profile.public_bootstrap.unwrap_or(false),
+            known_peers_file,
+            state_dir,
+            identity_file: args
+                .identity
+                .clone()
+                .or(profile.identity_file)
+                .map(|p| expand_home(&p)),
+            output: args.output.or(profile.output).unwrap_or_default(),
+            debug: switch(args.debug, args.no_debug)
+                .or(profile.debug)
+                .unwrap_or(false),
+            notify: profile.notify.unwrap_or_default(),
+            blocked_response: profile.blocked_response.unwrap_or_default(),
+            rich_text: profile.rich_text.unwrap_or(true),
+            presence: switch(args.presence, args.no_presence)
+                .or(profile.presence)
+                .unwrap_or(false),
+            metrics_addr: args.metrics_addr.or(profile.metrics_addr),
+        })
+    }
+
+    /// Room that is active at startup.
+    pub fn room(&self) -> &str {
+        &self.rooms[0]
+    }
+
+    /// Path of a persisted state file for this profile.
+    pub fn state_file(&self, name: &str) -> PathBuf {
+        self.state_dir.join(name)
+    }
+}
+
+impl ConfigFile {
+    /// Read the config file. A missing file is only an error when the path
+    /// was given explicitly.
+    pub fn load(path: &Path, explicit: bool) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read config file {}", path.display()))
+            }
+        }
+    }
+}
+
+/// A `--flag`/`--no-flag` pair as an override; `None` when neither was
+/// given, so the profile decides.
+fn switch(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// `~/.config/six7` (or the platform equivalent).
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("six7")
+}
+
+/// `~/.local/share/six7` (or the platform equivalent) for state files.
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("six7")
+}
+
+pub fn default_config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir().map_or_else(|| path.to_path_buf(), |h| h.join(rest)),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// `six7 config show`: print the effective configuration as TOML.
+pub fn show(settings: &Settings) -> Result<()> {
+    println!("# config file: {}", settings.config_path.display());
+    print!(
+        "{}",
+        toml::to_string_pretty(settings).context("Failed to render config")?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const FILE: &str = r#"
+default_profile = "work"
+
+[profiles.work]
+name = "Alice"
+rooms = ["dev", "ops"]
+port = 4433
+debug = true
+presence = true
+output = "plain"
+
+[profiles.home]
+state_dir = "/tmp/six7-home"
+"#;
+
+    fn settings(argv: &[&str]) -> Settings {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+        let args =
+            Args::try_parse_from(std::iter::once("six7").chain(argv.iter().copied())).unwrap();
+        Settings::merge(PathBuf::from("config.toml"), &file, &args).unwrap()
+    }
+
+    #[test]
+    fn merge_precedence_cases() {
+        let cases = [
+            // argv, name, active room, port, debug, presence
+            (vec![], "Alice", "dev", 4433, true, true),
+            (vec!["--name", "Bob"], "Bob", "dev", 4433, true, true),
+            (vec!["--room", "ops"], "Alice", "ops", 4433, true, true),
+            (vec!["--room", "qa"], "Alice", "qa", 4433, true, true),
+            (vec!["--port", "0"], "Alice", "dev", 0, true, true),
+            (vec!["--no-debug"], "Alice", "dev", 4433, false, true),
+            (vec!["--no-presence"], "Alice", "dev", 4433, true, false),
+            (
+                vec!["-P", "home", "-d"],
+                DEFAULT_NAME,
+                DEFAULT_ROOM,
+                0,
+                true,
+                false,
+            ),
+        ];
+        for (argv, name, room, port, debug, presence) in cases {
+            let s = settings(&argv);
+            assert_eq!(s.name, name, "name for {argv:?}");
+            assert_eq!(s.room(), room, "room for {argv:?}");
+            assert_eq!(s.port, port, "port for {argv:?}");
+            assert_eq!(s.debug, debug, "debug for {argv:?}");
+            assert_eq!(s.presence, presence, "presence for {argv:?}");
+        }
+    }
+
+    #[test]
+    fn merge_keeps_other_rooms() {
+        assert_eq!(settings(&[]).rooms, ["dev", "ops"]);
+        assert_eq!(settings(&["--room", "ops"]).rooms, ["ops", "dev"]);
+        assert_eq!(settings(&["--room", "qa"]).rooms, ["qa", "dev", "ops"]);
+    }
+
+    #[test]
+    fn switch_cases() {
+        let cases = [
+            ((false, false), None),
+            ((true, false), Some(true)),
+            ((false, true), Some(false)),
+        ];
+        for ((on, off), expected) in cases {
+            assert_eq!(switch(on, off), expected, "switch({on}, {off})");
+        }
+    }
+
+    #[test]
+    fn later_flag_wins() {
+        assert!(!settings(&["--debug", "--no-debug"]).debug);
+        assert!(settings(&["--no-debug", "--debug"]).debug);
+    }
+
+    #[test]
+    fn known_peers_live_in_state_dir() {
+        let s = settings(&["-P", "home"]);
+        assert_eq!(s.state_dir, PathBuf::from("/tmp/six7-home"));
+        assert_eq!(s.known_peers_file, s.state_dir.join("known_peers.txt"));
+        let s = settings(&[]);
+        assert_eq!(s.known_peers_file, s.state_file("known_peers.txt"));
+    }
+
+    #[test]
+    fn config_show_leaves_join_targets_unexpanded() {
+        let s = settings(&["--join", "@-", "config", "show"]);
+        assert_eq!(s.bootstrap_peers, ["@-"]);
+    }
+
+    #[test]
+    fn invalid_rooms_are_an_error() {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+        for room in ["", "  ", "lob\u{202E}by", "lobby\n[dev] admin: hi"] {
+            let args = Args::try_parse_from(["six7", "--room", room]).unwrap();
+            assert!(
+                Settings::merge(PathBuf::new(), &file, &args).is_err(),
+                "{room:?}"
+            );
+        }
+        let file: ConfigFile = toml::from_str("[profiles.bad]\nrooms = [\"\\u001b[2J\"]").unwrap();
+        let args = Args::try_parse_from(["six7", "-P", "bad"]).unwrap();
+        assert!(Settings::merge(PathBuf::new(), &file, &args).is_err());
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        let file: ConfigFile = toml::from_str(FILE).unwrap();
+        let args = Args::try_parse_from(["six7", "-P", "nope"]).unwrap();
+        assert!(Settings::merge(PathBuf::new(), &file, &args).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Contacts and safety-number verification
+//!
+//! Two users compare a safety number derived from both identities over a
+//! trusted channel (in person, on a call). When they match, each marks the
+//! other as verified and the pairing of name and identity is remembered.
+//! Seeing a verified contact's name on a different identity afterwards is
+//! reported loudly, since it is what an impersonation attempt looks like.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::current_timestamp_ms;
+use crate::sanitize::skeleton;
+
+const SAFETY_DOMAIN: &[u8] = b"six7-safety-number-v1";
+
+/// 64 visually distinct emoji; each safety emoji encodes 6 bits.
+const EMOJI: [&str; 64] = [
+    "🐶", "🐱", "🦁", "🐴", "🦄", "🐷", "🐘", "🐰", "🐼", "🐓", "🐧", "🐢", "🐟", "🐙", "🦋", "🌷",
+    "🌳", "🌵", "🍄", "🌏", "🌙", "☁️", "🔥", "🍌", "🍎", "🍓", "🌽", "🍕", "🎂", "❤️", "😀", "🤖",
+    "🎩", "👓", "🔧", "🎅", "👍", "☂️", "⌛", "⏰", "🎁", "💡", "📕", "✏️", "📎", "✂️", "🔒", "🔑",
+    "🔨", "☎️", "🏁", "🚂", "🚲", "✈️", "🚀", "🏆", "⚽", "🎸", "🎺", "🔔", "⚓", "🎧", "📁", "📌",
+];
+
+/// What we know about one peer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Contact {
+    /// Display name at the time of verification
+    pub name: String,
+    #[serde(default)]
+    pub verified: bool,
+    /// Unix epoch milliseconds when the safety number was confirmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_at_ms: Option<i64>,
+}
+
+/// Contacts keyed by identity, persisted as TOML.
+#[derive(Debug, Default)]
+pub struct Contacts {
+    path: PathBuf,
+    contacts: BTreeMap<String, Contact>,
+}
+
+impl Contacts {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contacts = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid contacts file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            contacts,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let text = toml::to_string_pretty(&self.contacts).context("Failed to encode contacts")?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn is_verified(&self, identity: &str) -> bool {
+        self.contacts.get(identity).is_some_and(|c| c.verified)
+    }
+
+    /// Mark `identity` as verified under `name`.
+    pub fn verify(&mut self, identity: &str, name: &str) {
+        let contact = self.contacts.entry(identity.to_string()).or_default();
+        contact.name = name.to_string();
+        contact.verified = true;
+        contact.verified_at_ms = Some(current_timestamp_ms());
+    }
+
+    /// Withdraw verification. Returns `false` if `identity` was not verified.
+    pub fn unverify(&mut self, identity: &str) -> bool {
+        match self.contacts.get_mut(identity) {
+            Some(c) if c.verified => {
+                c.verified = false;
+                c.verified_at_ms = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The verified contact that already goes by `name`, if it is not
+    /// `identity`. Names are compared case-insensitively.
+    pub fn name_conflict(&self, name: &str, identity: &str) -> Option<(&str, &Contact)> {
+        self.contacts
+            .iter()
+            .find(|(id, c)| c.verified && *id != identity && skeleton(&c.name) == skeleton(name))
+            .map(|(id, c)| (id.as_str(), c))
+    }
+}
+
+fn safety_input(a: &str, b: &str) -> Vec<u8> {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut input = SAFETY_DOMAIN.to_vec();
+    input.extend_from_slice(first.as_bytes());
+    input.extend_from_slice(second.as_bytes());
+    input
+}
+
+/// 60-digit safety number for the pair `a`/`b`, in twelve groups of five.
+/// Both sides compute the same number regardless of argument order.
+pub fn safety_number(a: &str, b: &str) -> String {
+    let digest = Sha512::digest(safety_input(a, b));
+    digest
+        .chunks(5)
+        .take(12)
+        .map(|chunk| {
+            let n = chunk.iter().fold(0u64, |acc, &x| (acc << 8) | x as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Eight emoji for the pair `a`/`b`, easier to compare over a call.
+pub fn safety_emoji(a: &str, b: &str) -> String {
+    let digest = Sha256::digest(safety_input(a, b));
+    digest
+        .iter()
+        .take(8)
+        .map(|x| EMOJI[(*x & 0x3f) as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,259 @@
+//! Document attachments
+//!
+//! Text too large for one message (a long `/paste`) can be sent as a
+//! `document` message instead. The text is gzip-compressed and base64
+//! encoded inside a JSON payload, which lets a log of a few hundred
+//! kilobytes fit under `MAX_MESSAGE_SIZE_BYTES`. Receivers show a summary
+//! and a short preview, and hold the last few documents in memory so that
+//! `/save` can write one to the profile's downloads directory. Nothing is
+//! written without `/save`, no file is ever overwritten, and the directory
+//! stops accepting documents at [`MAX_DOWNLOADS_BYTES`].
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::sanitize;
+
+/// Largest document accepted after decompression.
+pub const MAX_DOCUMENT_BYTES: usize = 4 * 1024 * 1024;
+/// Most bytes `/save` lets the downloads directory hold.
+pub const MAX_DOWNLOADS_BYTES: u64 = 64 * 1024 * 1024;
+/// Received documents held for `/save`.
+pub const MAX_HELD: usize = 16;
+/// Lines of a received document shown in the chat.
+const PREVIEW_LINES: usize = 3;
+const ENCODING: &str = "gzip+base64";
+
+static DOWNLOADS: OnceLock<PathBuf> = OnceLock::new();
+
+/// Select where received documents are saved. Only the first call has an
+/// effect.
+pub fn init(dir: PathBuf) {
+    let _ = DOWNLOADS.set(dir);
+}
+
+/// Content of a `document` message (JSON).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentPayload {
+    pub file_name: String,
+    pub mime_type: String,
+    /// Size of the document before compression
+    pub size: u64,
+    /// How `data` is encoded; only `gzip+base64` is defined
+    pub encoding: String,
+    pub data: String,
+}
+
+impl DocumentPayload {
+    pub fn from_text(file_name: &str, text: &str) -> Result<Self> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(text.as_bytes())?;
+        let compressed = encoder.finish().context("Failed to compress document")?;
+        Ok(Self {
+            file_name: file_name.to_string(),
+            mime_type: "text/plain".to_string(),
+            size: text.len() as u64,
+            encoding: ENCODING.to_string(),
+            data: STANDARD.encode(compressed),
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        serde_json::from_str(content).context("Malformed document")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("document serialization is infallible")
+    }
+
+    /// The document's bytes, refusing anything that inflates past
+    /// [`MAX_DOCUMENT_BYTES`].
+    pub fn contents(&self) -> Result<Vec<u8>> {
+        if self.encoding != ENCODING {
+            anyhow::bail!("Unsupported document encoding {}", self.encoding);
+        }
+        let compressed = STANDARD
+            .decode(&self.data)
+            .context("Document data is not base64")?;
+        let mut contents = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .take(MAX_DOCUMENT_BYTES as u64 + 1)
+            .read_to_end(&mut contents)
+            .context("Document data is not gzip")?;
+        if contents.len() > MAX_DOCUMENT_BYTES {
+            anyhow::bail!("Document is larger than {} bytes", MAX_DOCUMENT_BYTES);
+        }
+        Ok(contents)
+    }
+
+    /// One line describing the document, e.g. `📄 paste.txt (12.5 KB)`.
+    pub fn summary(&self) -> String {
+        let name = sanitize::name(&self.file_name);
+        let size = self.size as f64;
+        if size < 1024.0 {
+            format!("📄 {name} ({} B)", self.size)
+        } else if size < 1024.0 * 1024.0 {
+            format!("📄 {name} ({:.1} KB)", size / 1024.0)
+        } else {
+            format!("📄 {name} ({:.1} MB)", size / (1024.0 * 1024.0))
+        }
+    }
+}
+
+/// The first lines of a document's text, sanitized.
+pub fn preview(payload: &DocumentPayload) -> Result<String> {
+    let contents = payload.contents()?;
+    let text = String::from_utf8_lossy(&contents);
+    let preview = text
+        .lines()
+        .take(PREVIEW_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(sanitize::text(&preview))
+}
+
+/// Recently received documents, by message id, oldest first.
+#[derive(Debug, Default)]
+pub struct Inbox {
+    held: VecDeque<(String, DocumentPayload)>,
+}
+
+pub type SharedInbox = Arc<RwLock<Inbox>>;
+
+impl Inbox {
+    /// Hold the document of message `message_id`, dropping the oldest one
+    /// past [`MAX_HELD`].
+    pub fn hold(&mut self, message_id: &str, payload: DocumentPayload) {
+        if self.get(message_id).is_some() {
+            return;
+        }
+        if self.held.len() == MAX_HELD {
+            self.held.pop_front();
+        }
+        self.held.push_back((message_id.to_string(), payload));
+    }
+
+    pub fn get(&self, message_id: &str) -> Option<&DocumentPayload> {
+        self.held
+            .iter()
+            .find(|(id, _)| id == message_id)
+            .map(|(_, payload)| payload)
+    }
+}
+
+/// Save a received document to the downloads directory as
+/// `<id prefix>-<file name>` and return the path. Fails rather than
+/// overwrite a file or grow the directory past [`MAX_DOWNLOADS_BYTES`].
+pub fn save(message_id: &str, payload: &DocumentPayload) -> Result<PathBuf> {
+    let dir = DOWNLOADS.get().context("No downloads directory")?;
+    let contents = payload.contents()?;
+    let base: String = sanitize::name(&payload.file_name)
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = base.trim_start_matches('.');
+    let base = if base.is_empty() { "document" } else { base };
+    let prefix: String = message_id
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(8)
+        .collect();
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let used = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok()?.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum::<u64>();
+    if used + contents.len() as u64 > MAX_DOWNLOADS_BYTES {
+        anyhow::bail!(
+            "{} is full ({used} of {MAX_DOWNLOADS_BYTES} bytes used); delete some files first",
+            dir.display()
+        );
+    }
+    let path = dir.join(format!("{prefix}-{base}"));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(&contents))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_and_preview() {
+        let text = "first\nsecond\nthird\nfourth";
+        let payload = DocumentPayload::from_text("notes.txt", text).unwrap();
+        let parsed = DocumentPayload::parse(&payload.to_json()).unwrap();
+        assert_eq!(parsed.contents().unwrap(), text.as_bytes());
+        assert_eq!(preview(&parsed).unwrap(), "first\n  second\n  third");
+        assert_eq!(parsed.summary(), "📄 notes.txt (25 B)");
+    }
+
+    #[test]
+    fn oversized_documents_are_refused() {
+        let bomb = "0".repeat(MAX_DOCUMENT_BYTES + 1);
+        let payload = DocumentPayload::from_text("bomb.txt", &bomb).unwrap();
+        assert!(payload.to_json().len() < 64 * 1024);
+        assert!(payload.contents().is_err());
+    }
+
+    #[test]
+    fn inbox_keeps_the_latest_documents() {
+        let payload = DocumentPayload::from_text("a.txt", "a").unwrap();
+        let mut inbox = Inbox::default();
+        for i in 0..MAX_HELD + 2 {
+            inbox.hold(&format!("id{i}"), payload.clone());
+        }
+        inbox.hold("id5", payload.clone());
+        assert_eq!(inbox.held.len(), MAX_HELD);
+        assert!(inbox.get("id0").is_none());
+        assert!(inbox.get("id1").is_none());
+        assert!(inbox.get(&format!("id{}", MAX_HELD + 1)).is_some());
+    }
+
+    #[test]
+    fn save_never_overwrites() {
+        let dir = std::env::temp_dir().join(format!("six7-downloads-{}", std::process::id()));
+        init(dir.clone());
+        let _ = std::fs::remove_file(dir.join("abcdef01-_.._.bashrc"));
+        let payload = DocumentPayload::from_text("../../.bashrc", "echo hi").unwrap();
+        let path = save("abcdef0123", &payload).unwrap();
+        assert_eq!(path, dir.join("abcdef01-_.._.bashrc"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "echo hi");
+        assert!(save("abcdef0123", &payload).is_err());
+    }
+
+    #[test]
+    fn save_prefix_is_ascii_whatever_the_id() {
+        let dir = std::env::temp_dir().join(format!("six7-downloads-{}", std::process::id()));
+        init(dir.clone());
+        let _ = std::fs::remove_file(dir.join("aaaaabb-notes.txt"));
+        let payload = DocumentPayload::from_text("notes.txt", "hi").unwrap();
+        let path = save("aaaaaé/../bb", &payload).unwrap();
+        assert_eq!(path, dir.join("aaaaabb-notes.txt"));
+    }
+}
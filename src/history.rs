@@ -0,0 +1,545 @@
+//! Message history
+//!
+//! Every room and DM conversation has an append-only JSON-lines log of
+//! events (messages, edits, deletions, reactions) under the profile's state
+//! directory. Replaying the log yields the current view of the
+//! conversation: edited messages carry their latest text, deleted ones
+//! become tombstones, and reactions are tallied per message. Logs are
+//! compacted when they grow well past [`MAX_HISTORY`] events, keeping the
+//! most recent messages.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+/// Messages kept per conversation.
+pub const MAX_HISTORY: usize = 500;
+/// Length of the id prefix shown to users.
+pub const SHORT_ID_LEN: usize = 6;
+
+/// Whether `id` is a message id as clients make them: 32 hex digits, or
+/// a hyphenated UUID. Peers choose the ids of their messages and of the
+/// messages they refer to, so anything else is refused on receipt.
+pub fn valid_id(id: &str) -> bool {
+    let hex = |s: &str| s.bytes().all(|b| b.is_ascii_hexdigit());
+    match id.len() {
+        32 => hex(id),
+        36 => {
+            let groups: Vec<&str> = id.split('-').collect();
+            groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]) && groups.iter().all(|g| hex(g))
+        }
+        _ => false,
+    }
+}
+
+/// The first [`SHORT_ID_LEN`] characters of `id`, as shown to users.
+pub fn short(id: &str) -> &str {
+    id.char_indices()
+        .nth(SHORT_ID_LEN)
+        .map_or(id, |(end, _)| &id[..end])
+}
+
+/// One message as it currently stands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub id: String,
+    /// Sender identity
+    pub from: String,
+    /// Sender display name when the message arrived
+    pub name: String,
+    pub timestamp: i64,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// Id of the message that started the thread this reply belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    #[serde(default)]
+    pub edited: bool,
+    /// Tombstone: the author deleted the message
+    #[serde(default)]
+    pub deleted: bool,
+    /// Identities that reacted, per emoji
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Entry {
+    pub fn new(id: &str, from: &str, name: &str, timestamp: i64, content: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            from: from.to_string(),
+            name: name.to_string(),
+            timestamp,
+            content: content.to_string(),
+            reply_to: None,
+            thread: None,
+            edited: false,
+            deleted: false,
+            reactions: BTreeMap::new(),
+        }
+    }
+
+    pub fn short_id(&self) -> &str {
+        short(&self.id)
+    }
+
+    /// Reaction tally such as `👍 2  🎉 1`, empty without reactions.
+    pub fn reaction_summary(&self) -> String {
+        self.reactions
+            .iter()
+            .map(|(emoji, who)| format!("{emoji} {}", who.len()))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    fn add_reaction(&mut self, from: &str, emoji: &str) -> bool {
+        self.reactions
+            .entry(emoji.to_string())
+            .or_default()
+            .insert(from.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum Event {
+    Message(Entry),
+    Edit {
+        id: String,
+        content: String,
+    },
+    Delete {
+        id: String,
+    },
+    React {
+        id: String,
+        from: String,
+        emoji: String,
+    },
+}
+
+/// Outcome of applying an edit or deletion.
+#[derive(Debug)]
+pub enum Change {
+    /// The message was updated; holds its new state
+    Applied(Entry),
+    /// The message is not in our history (sent before we joined) or
+    /// has been deleted
+    Unknown,
+    /// The message belongs to someone else
+    NotAuthor,
+}
+
+/// Conversation key for a room.
+pub fn room_key(room: &str) -> String {
+    format!("room/{room}")
+}
+
+/// Conversation key for a DM thread with `identity`.
+pub fn dm_key(identity: &str) -> String {
+    format!("dm/{identity}")
+}
+
+/// Conversations loaded so far, keyed by conversation key.
+#[derive(Debug)]
+pub struct History {
+    dir: PathBuf,
+    conversations: HashMap<String, Vec<Entry>>,
+}
+
+pub type SharedHistory = Arc<RwLock<History>>;
+
+impl History {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            conversations: HashMap::new(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let safe: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .take(64)
+            .collect();
+        let hash = hex::encode(Sha256::digest(key.as_bytes()));
+        self.dir.join(format!("{safe}-{}.jsonl", &hash[..8]))
+    }
+
+    /// Entries of `key`, loading its log on first use.
+    pub fn entries(&mut self, key: &str) -> &[Entry] {
+        self.load(key)
+    }
+
+    fn load(&mut self, key: &str) -> &mut Vec<Entry> {
+        if !self.conversations.contains_key(key) {
+            let path = self.path(key);
+            let (entries, events) = replay(&path);
+            if events > 2 * MAX_HISTORY {
+                if let Err(e) = compact(&path, &entries) {
+                    tracing::warn!("failed to compact {}: {e:#}", path.display());
+                }
+            }
+            self.conversations.insert(key.to_string(), entries);
+        }
+        self.conversations
+            .get_mut(key)
+            .expect("conversation was just loaded")
+    }
+
+    fn append(&self, key: &str, event: &Event) -> Result<()> {
+        let path = self.path(key);
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let mut line = serde_json::to_string(event).context("Failed to encode history")?;
+        line.push('\n');
+        write_private(&path, &line, true)
+    }
+
+    /// Store a new message. Messages already recorded are ignored.
+    pub fn record(&mut self, key: &str, entry: Entry) -> Result<()> {
+        let entries = self.load(key);
+        if entries.iter().any(|e| e.id == entry.id) {
+            return Ok(());
+        }
+        entries.push(entry.clone());
+        let excess = entries.len().saturating_sub(MAX_HISTORY);
+        entries.drain(..excess);
+        self.append(key, &Event::Message(entry))
+    }
+
+    pub fn get(&mut self, key: &str, id: &str) -> Option<&Entry> {
+        self.load(key).iter().find(|e| e.id == id)
+    }
+
+    /// Replace the text of message `id` on behalf of `from`. Deleted
+    /// messages stay deleted.
+    pub fn edit(&mut self, key: &str, id: &str, from: &str, content: &str) -> Result<Change> {
+        let Some(entry) = self.load(key).iter_mut().find(|e| e.id == id && !e.deleted) else {
+            return Ok(Change::Unknown);
+        };
+        if entry.from != from {
+            return Ok(Change::NotAuthor);
+        }
+        entry.content = content.to_string();
+        entry.edited = true;
+        let updated = entry.clone();
+        self.append(
+            key,
+            &Event::Edit {
+                id: id.to_string(),
+                content: content.to_string(),
+            },
+        )?;
+        Ok(Change::Applied(updated))
+    }
+
+    /// Turn message `id` into a tombstone on behalf of `from`.
+    pub fn delete(&mut self, key: &str, id: &str, from: &str) -> Result<Change> {
+        let Some(entry) = self.load(key).iter_mut().find(|e| e.id == id) else {
+            return Ok(Change::Unknown);
+        };
+        if entry.from != from {
+            return Ok(Change::NotAuthor);
+        }
+        entry.content.clear();
+        entry.deleted = true;
+        let updated = entry.clone();
+        self.append(key, &Event::Delete { id: id.to_string() })?;
+        Ok(Change::Applied(updated))
+    }
+
+    /// Record `from`'s `emoji` reaction to message `id`. Reacting twice
+    /// with the same emoji counts once.
+    pub fn react(&mut self, key: &str, id: &str, from: &str, emoji: &str) -> Result<Change> {
+        let Some(entry) = self.load(key).iter_mut().find(|e| e.id == id) else {
+            return Ok(Change::Unknown);
+        };
+        if !entry.add_reaction(from, emoji) {
+            return Ok(Change::Applied(entry.clone()));
+        }
+        let updated = entry.clone();
+        self.append(
+            key,
+            &Event::React {
+                id: id.to_string(),
+                from: from.to_string(),
+                emoji: emoji.to_string(),
+            },
+        )?;
+        Ok(Change::Applied(updated))
+    }
+
+    /// The root of thread `root` followed by its replies, oldest first.
+    /// The root is missing if it predates our history.
+    pub fn thread(&mut self, key: &str, root: &str) -> Vec<Entry> {
+        self.load(key)
+            .iter()
+            .filter(|e| e.id == root || e.thread.as_deref() == Some(root))
+            .cloned()
+            .collect()
+    }
+
+    /// Replies per thread root in `key`.
+    pub fn reply_counts(&mut self, key: &str) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for root in self.load(key).iter().filter_map(|e| e.thread.clone()) {
+            *counts.entry(root).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The `n`th most recent message (1 = the latest).
+    pub fn nth_latest(&mut self, key: &str, n: usize) -> Option<Entry> {
+        let entries = self.load(key);
+        n.checked_sub(1)
+            .and_then(|back| entries.iter().rev().nth(back))
+            .cloned()
+    }
+
+    /// The message whose id starts with `prefix`, if exactly one does.
+    pub fn find_prefix(&mut self, key: &str, prefix: &str) -> Result<Entry> {
+        let prefix = prefix.to_lowercase();
+        let matches: Vec<&Entry> = self
+            .load(key)
+            .iter()
+            .filter(|e| e.id.starts_with(&prefix))
+            .collect();
+        match matches[..] {
+            [entry] => Ok(entry.clone()),
+            [] => anyhow::bail!("No message {prefix} in this conversation"),
+            _ => anyhow::bail!(
+                "{prefix} matches {} messages; use more digits",
+                matches.len()
+            ),
+        }
+    }
+
+    /// The latest message by `from` that has not been deleted.
+    pub fn last_from(&mut self, key: &str, from: &str) -> Option<Entry> {
+        self.load(key)
+            .iter()
+            .rev()
+            .find(|e| e.from == from && !e.deleted)
+            .cloned()
+    }
+}
+
+/// Rebuild a conversation from its log. Unreadable lines are skipped.
+/// Returns the entries and the number of events read.
+fn replay(path: &Path) -> (Vec<Entry>, usize) {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return (Vec::new(), 0);
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut events = 0;
+    for line in text.lines() {
+        let Ok(event) = serde_json::from_str::<Event>(line) else {
+            continue;
+        };
+        events += 1;
+        match event {
+            Event::Message(entry) => entries.push(entry),
+            Event::Edit { id, content } => {
+                if let Some(e) = entries.iter_mut().find(|e| e.id == id && !e.deleted) {
+                    e.content = content;
+                    e.edited = true;
+                }
+            }
+            Event::Delete { id } => {
+                if let Some(e) = entries.iter_mut().find(|e| e.id == id) {
+                    e.content.clear();
+                    e.deleted = true;
+                }
+            }
+            Event::React { id, from, emoji } => {
+                if let Some(e) = entries.iter_mut().find(|e| e.id == id) {
+                    e.add_reaction(&from, &emoji);
+                }
+            }
+        }
+    }
+    let excess = entries.len().saturating_sub(MAX_HISTORY);
+    entries.drain(..excess);
+    (entries, events)
+}
+
+/// Rewrite a log as one message event per current entry.
+fn compact(path: &Path, entries: &[Entry]) -> Result<()> {
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&serde_json::to_string(&Event::Message(entry.clone()))?);
+        text.push('\n');
+    }
+    let tmp = path.with_extension("jsonl.tmp");
+    write_private(&tmp, &text, false)?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+/// Append to (or replace) a file readable only by the owner.
+fn write_private(path: &Path, text: &str, append: bool) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut f| f.write_all(text.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history in a fresh directory under the system temp dir.
+    fn scratch(name: &str) -> History {
+        let dir = std::env::temp_dir().join(format!("six7-history-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        History::new(&dir)
+    }
+
+    fn message(id: &str, from: &str) -> Entry {
+        Entry::new(id, from, from, 0, &format!("hello from {from}"))
+    }
+
+    #[test]
+    fn replay_restores_edits_deletions_and_reactions() {
+        let key = room_key("dev");
+        let mut history = scratch("replay");
+        history.record(&key, message("aa01", "alice")).unwrap();
+        history.record(&key, message("bb02", "bob")).unwrap();
+        history.record(&key, message("aa01", "alice")).unwrap();
+        history.edit(&key, "aa01", "alice", "fixed").unwrap();
+        history.delete(&key, "bb02", "bob").unwrap();
+        history.react(&key, "aa01", "bob", "👍").unwrap();
+        history.react(&key, "aa01", "bob", "👍").unwrap();
+
+        let mut reloaded = History::new(&history.dir);
+        let entries = reloaded.entries(&key);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].content, "fixed");
+        assert!(entries[0].edited);
+        assert_eq!(entries[0].reaction_summary(), "👍 1");
+        assert!(entries[1].deleted);
+        assert!(entries[1].content.is_empty());
+    }
+
+    #[test]
+    fn only_the_author_may_change_a_message() {
+        let key = dm_key("bob");
+        let mut history = scratch("author");
+        history.record(&key, message("aa01", "alice")).unwrap();
+        let cases = [
+            history.edit(&key, "aa01", "bob", "mine now").unwrap(),
+            history.delete(&key, "aa01", "bob").unwrap(),
+        ];
+        for change in cases {
+            assert!(matches!(change, Change::NotAuthor), "{change:?}");
+        }
+        assert!(matches!(
+            history.edit(&key, "ffff", "alice", "?").unwrap(),
+            Change::Unknown
+        ));
+        assert_eq!(
+            history.get(&key, "aa01").unwrap().content,
+            "hello from alice"
+        );
+    }
+
+    #[test]
+    fn deleted_messages_cannot_be_edited() {
+        let key = room_key("dev");
+        let mut history = scratch("tombstone");
+        history.record(&key, message("aa01", "alice")).unwrap();
+        history.delete(&key, "aa01", "alice").unwrap();
+        assert!(matches!(
+            history.edit(&key, "aa01", "alice", "back again").unwrap(),
+            Change::Unknown
+        ));
+
+        let mut reloaded = History::new(&history.dir);
+        let entry = reloaded.get(&key, "aa01").unwrap();
+        assert!(entry.deleted);
+        assert!(entry.content.is_empty());
+    }
+
+    #[test]
+    fn id_cases() {
+        let cases = [
+            ("0123456789abcdef0123456789ABCDEF", true),
+            ("550e8400-e29b-41d4-a716-446655440000", true),
+            ("550e8400e29b-41d4-a716-4466554400000", false),
+            ("0123456789abcdef0123456789abcde", false),
+            ("0123456789abcdef0123456789abcdeg", false),
+            ("aaaaaé", false),
+            ("aaaaaéaaaaaaaaaaaaaaaaaaaaaaaaa", false),
+            ("", false),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(valid_id(id), expected, "valid_id({id:?})");
+        }
+    }
+
+    #[test]
+    fn short_ids_respect_char_boundaries() {
+        let cases = [
+            ("0123456789abcdef", "012345"),
+            ("abc", "abc"),
+            ("aaaaaé", "aaaaaé"),
+            ("aaaaaéé", "aaaaaé"),
+            ("ééééééé", "éééééé"),
+        ];
+        for (id, expected) in cases {
+            assert_eq!(short(id), expected, "short({id:?})");
+        }
+    }
+
+    #[test]
+    fn multibyte_ids_replay_without_panicking() {
+        let key = room_key("dev");
+        let mut history = scratch("multibyte");
+        history.record(&key, message("aaaaaéé", "mallory")).unwrap();
+        let mut reloaded = History::new(&history.dir);
+        let entry = &reloaded.entries(&key)[0];
+        assert_eq!(entry.short_id(), "aaaaaé");
+    }
+
+    #[test]
+    fn long_logs_are_compacted_to_the_latest_messages() {
+        let key = room_key("busy");
+        let mut history = scratch("compact");
+        for i in 0..2 * MAX_HISTORY + 10 {
+            history
+                .record(&key, message(&format!("{i:06}"), "alice"))
+                .unwrap();
+        }
+
+        let mut reloaded = History::new(&history.dir);
+        let entries = reloaded.entries(&key);
+        assert_eq!(entries.len(), MAX_HISTORY);
+        assert_eq!(entries[0].id, format!("{:06}", MAX_HISTORY + 10));
+        let lines = std::fs::read_to_string(history.path(&key)).unwrap();
+        assert_eq!(lines.lines().count(), MAX_HISTORY);
+    }
+}
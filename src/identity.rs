@@ -0,0 +1,47 @@
+//! Persistent node identity
+//!
+//! Without an identity file every launch mines a fresh Ed25519 identity, so
+//! contacts lose track of us on restart. The file stores the hex-encoded
+//! secret key; it is created on first use with owner-only permissions.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use korium::Keypair;
+
+/// Load the keypair stored at `path`, if the file exists.
+pub fn load(path: &Path) -> Result<Option<Keypair>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read identity {}", path.display()))
+        }
+    };
+    let bytes: [u8; 32] = hex::decode(text.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .with_context(|| format!("Identity file {} is not a 32-byte hex key", path.display()))?;
+    Ok(Some(Keypair::from_secret_key_bytes(&bytes)))
+}
+
+/// Write `keypair` to `path`, readable only by the current user.
+pub fn save(path: &Path, keypair: &Keypair) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write identity {}", path.display()))?;
+    std::io::Write::write_all(
+        &mut file,
+        hex::encode(keypair.secret_key_bytes()).as_bytes(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,340 @@
+//! Shareable invite codes
+//!
+//! An invite code packs a bootstrap peer (address + identity), an optional
+//! room and an optional expiry into a checksummed Crockford base32 string:
+//!
+//! ```text
+//! SIX7-<base32(postcard(payload) || sha256(payload)[..4])>
+//! ```
+//!
+//! The alphabet is case-insensitive and avoids `I`, `L`, `O` and `U`, and
+//! the uppercase form uses QR alphanumeric mode, so codes survive being
+//! read aloud, retyped or scanned.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{current_timestamp_ms, MAX_IDENTITY_LENGTH};
+
+pub const INVITE_PREFIX: &str = "SIX7-";
+const INVITE_VERSION: u8 = 1;
+const CHECKSUM_LEN: usize = 4;
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Decoded invite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    /// Peer address, e.g. `192.168.1.100:4433`
+    pub addr: String,
+    /// Peer identity (64 hex characters)
+    pub identity: String,
+    /// Room to join on arrival
+    pub room: Option<String>,
+    /// Unix epoch milliseconds after which the code is rejected
+    pub expires_at_ms: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum PeerAddr {
+    V4([u8; 4], u16),
+    V6([u8; 16], u16),
+    Host(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct InvitePayload {
+    version: u8,
+    addr: PeerAddr,
+    identity: [u8; 32],
+    room: Option<String>,
+    /// Unix epoch seconds (seconds keep the varint short)
+    expires_at_s: Option<u64>,
+}
+
+impl Invite {
+    pub fn new(
+        addr: &str,
+        identity: &str,
+        room: Option<&str>,
+        ttl: Option<Duration>,
+    ) -> Result<Self> {
+        Ok(Self {
+            addr: addr.to_string(),
+            identity: identity.to_string(),
+            room: room.map(str::to_string),
+            expires_at_ms: ttl.map(expires_at).transpose()?,
+        })
+    }
+
+    /// Encode as `SIX7-...`.
+    pub fn encode(&self) -> Result<String> {
+        let identity: [u8; 32] = hex::decode(&self.identity)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .context("Identity must be 64 hex characters")?;
+        let addr = match self.addr.parse::<SocketAddr>() {
+            Ok(sa) => match sa.ip() {
+                IpAddr::V4(ip) => PeerAddr::V4(ip.octets(), sa.port()),
+                IpAddr::V6(ip) => PeerAddr::V6(ip.octets(), sa.port()),
+            },
+            Err(_) => PeerAddr::Host(self.addr.clone()),
+        };
+        let payload = InvitePayload {
+            version: INVITE_VERSION,
+            addr,
+            identity,
+            room: self.room.clone(),
+            expires_at_s: self.expires_at_ms.map(|ms| (ms.max(0) / 1000) as u64),
+        };
+        let mut bytes = postcard::to_allocvec(&payload).context("Failed to encode invite")?;
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        Ok(format!("{INVITE_PREFIX}{}", base32_encode(&bytes)))
+    }
+
+    /// Decode and validate a `SIX7-...` code, rejecting expired invites.
+    pub fn decode(code: &str) -> Result<Self> {
+        let body = code
+            .trim()
+            .get(INVITE_PREFIX.len()..)
+            .filter(|_| is_invite_code(code))
+            .context("Invite codes start with SIX7-")?;
+        let bytes = base32_decode(body).context("Invite code contains invalid characters")?;
+        if bytes.len() <= CHECKSUM_LEN {
+            anyhow::bail!("Invite code is truncated");
+        }
+        let (payload, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        if Sha256::digest(payload)[..CHECKSUM_LEN] != *checksum {
+            anyhow::bail!("Invite code checksum mismatch (typo?)");
+        }
+        let payload: InvitePayload =
+            postcard::from_bytes(payload).context("Invite code is malformed")?;
+        if payload.version != INVITE_VERSION {
+            anyhow::bail!("Unsupported invite code version {}", payload.version);
+        }
+
+        let addr = match payload.addr {
+            PeerAddr::V4(ip, port) => SocketAddr::from((ip, port)).to_string(),
+            PeerAddr::V6(ip, port) => SocketAddr::from((ip, port)).to_string(),
+            PeerAddr::Host(host) => host,
+        };
+        let invite = Self {
+            addr,
+            identity: hex::encode(payload.identity),
+            room: payload.room,
+            expires_at_ms: payload
+                .expires_at_s
+                .map(|s| {
+                    i64::try_from(s)
+                        .ok()
+                        .and_then(|s| s.checked_mul(1000))
+                        .context("Invite code expiry is out of range")
+                })
+                .transpose()?,
+        };
+        debug_assert_eq!(invite.identity.len(), MAX_IDENTITY_LENGTH);
+        if invite
+            .expires_at_ms
+            .is_some_and(|exp| exp < current_timestamp_ms())
+        {
+            anyhow::bail!("Invite code has expired");
+        }
+        Ok(invite)
+    }
+}
+
+/// Whether `s` looks like an invite code (as opposed to `addr/identity`).
+pub fn is_invite_code(s: &str) -> bool {
+    s.trim()
+        .get(..INVITE_PREFIX.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(INVITE_PREFIX))
+}
+
+/// Render `data` as a terminal QR code using half-block characters.
+pub fn render_qr(data: &str) -> Result<String> {
+    let code = qrcode::QrCode::new(data.as_bytes()).context("Invite code too long for QR")?;
+    Ok(code
+        .render::<qrcode::render::unicode::Dense1x2>()
+        .quiet_zone(true)
+        .build())
+}
+
+/// Parse a TTL such as `90s`, `30m`, `12h` or `7d` (bare numbers are seconds).
+pub fn parse_ttl(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = digits
+        .parse()
+        .with_context(|| format!("Invalid duration: {s}"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => anyhow::bail!("Invalid duration unit in {s} (use s, m, h or d)"),
+    };
+    let secs = n
+        .checked_mul(unit_secs)
+        .with_context(|| format!("Duration too long: {s}"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Unix epoch milliseconds `ttl` from now, rejecting TTLs that do not fit.
+pub fn expires_at(ttl: Duration) -> Result<i64> {
+    i64::try_from(ttl.as_millis())
+        .ok()
+        .and_then(|ms| current_timestamp_ms().checked_add(ms))
+        .context("Duration too long")
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &b in bytes {
+        buffer = (buffer << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(CROCKFORD[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(CROCKFORD[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in s.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let value = CROCKFORD.iter().position(|&a| a as char == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "1a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f809";
+
+    #[test]
+    fn round_trip() {
+        let cases = [
+            Invite::new("192.168.1.100:4433", IDENTITY, None, None).unwrap(),
+            Invite::new("[2001:db8::1]:4433", IDENTITY, Some("lobby"), None).unwrap(),
+            Invite::new("chat.example.org:4433", IDENTITY, Some("dev"), None).unwrap(),
+            Invite::new(
+                "10.0.0.1:1",
+                IDENTITY,
+                Some("ops"),
+                Some(Duration::from_secs(3600)),
+            )
+            .unwrap(),
+        ];
+        for invite in cases {
+            let code = invite.encode().unwrap();
+            assert!(is_invite_code(&code), "{code}");
+            let decoded = Invite::decode(&code).unwrap();
+            // The expiry travels in whole seconds
+            let expected = Invite {
+                expires_at_ms: invite.expires_at_ms.map(|ms| ms / 1000 * 1000),
+                ..invite
+            };
+            assert_eq!(decoded, expected, "{code}");
+            assert_eq!(Invite::decode(&code.to_lowercase()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn checksum_catches_typos() {
+        let code = Invite::new("192.168.1.100:4433", IDENTITY, Some("lobby"), None)
+            .unwrap()
+            .encode()
+            .unwrap();
+        let body = code.len() - INVITE_PREFIX.len();
+        for i in [0, body / 2, body - 1] {
+            let mut chars: Vec<char> = code.chars().collect();
+            let at = INVITE_PREFIX.len() + i;
+            chars[at] = if chars[at] == 'Z' { 'Y' } else { 'Z' };
+            let typo: String = chars.into_iter().collect();
+            assert!(Invite::decode(&typo).is_err(), "{typo}");
+        }
+        assert!(Invite::decode("SIX7-").is_err());
+        assert!(Invite::decode("SIX7-U").is_err());
+        assert!(Invite::decode("192.168.1.100:4433").is_err());
+    }
+
+    #[test]
+    fn expiry() {
+        let expired = Invite {
+            expires_at_ms: Some(current_timestamp_ms() - 2000),
+            ..Invite::new("192.168.1.100:4433", IDENTITY, None, None).unwrap()
+        };
+        let err = Invite::decode(&expired.encode().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("expired"), "{err}");
+    }
+
+    #[test]
+    fn out_of_range_expiry_is_rejected() {
+        let payload = InvitePayload {
+            version: INVITE_VERSION,
+            addr: PeerAddr::V4([127, 0, 0, 1], 4433),
+            identity: [7; 32],
+            room: None,
+            expires_at_s: Some(u64::MAX),
+        };
+        let mut bytes = postcard::to_allocvec(&payload).unwrap();
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        let code = format!("{INVITE_PREFIX}{}", base32_encode(&bytes));
+        assert!(Invite::decode(&code).is_err());
+    }
+
+    #[test]
+    fn ttl_cases() {
+        let cases = [
+            ("90", Some(90)),
+            ("90s", Some(90)),
+            ("30m", Some(1800)),
+            ("12h", Some(43200)),
+            ("7d", Some(604800)),
+            ("7w", None),
+            ("", None),
+            ("h", None),
+            ("213503982334602d", None),
+            ("18446744073709551615d", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_ttl(input).ok().map(|d| d.as_secs()),
+                expected,
+                "parse_ttl({input:?})"
+            );
+        }
+        assert!(expires_at(Duration::from_secs(86400)).is_ok());
+        assert!(expires_at(Duration::from_secs(u64::MAX / 1000)).is_err());
+        assert!(Invite::new("a:1", IDENTITY, None, Some(Duration::from_secs(u64::MAX))).is_err());
+    }
+}
@@ -6,19 +6,60 @@
 //! Protocol Version: 1.3
 //! Binary message format using postcard serialization.
 
+mod blocklist;
+mod bootstrap;
+mod compose;
+mod config;
+mod contacts;
+mod document;
+mod e2e;
+mod history;
+mod identity;
+mod invite;
+mod markup;
+mod membership;
+mod mentions;
+mod metrics;
+mod moderation;
+mod notify;
+mod oneshot;
+mod presence;
+mod ratelimit;
+mod roster;
+mod sanitize;
+mod signing;
+mod stats;
+mod ui;
+mod unread;
+
 use std::collections::HashMap;
-use std::io::BufRead;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 use korium::Node;
 
+use blocklist::Blocklist;
+use config::{BlockedResponse, OutputMode, Settings};
+use contacts::Contacts;
+use document::{DocumentPayload, SharedInbox};
+use history::SharedHistory;
+use membership::{RoomInvite, SharedPrivateRooms};
+use moderation::{ModAction, ModKind, SharedModerationLog};
+use presence::{PresencePayload, SharedPresence};
+use ratelimit::{RateLimiter, Verdict};
+use signing::Authenticity;
+use stats::SharedStats;
+use unread::SharedReadMarkers;
+
 // ============================================================================
 // Six7 Message Protocol v1.3
 // ============================================================================
@@ -40,6 +81,16 @@ pub enum MessageType {
     Vibe,
     ReadReceipt,
     ProfileUpdate,
+    GroupJoin,
+    GroupLeave,
+    GroupKey,
+    Encrypted,
+    Moderation,
+    Reply,
+    Edit,
+    Delete,
+    Reaction,
+    Presence,
 }
 
 impl std::fmt::Display for MessageType {
@@ -58,6 +109,16 @@ impl std::fmt::Display for MessageType {
             MessageType::Vibe => write!(f, "vibe"),
             MessageType::ReadReceipt => write!(f, "readReceipt"),
             MessageType::ProfileUpdate => write!(f, "profileUpdate"),
+            MessageType::GroupJoin => write!(f, "groupJoin"),
+            MessageType::GroupLeave => write!(f, "groupLeave"),
+            MessageType::GroupKey => write!(f, "groupKey"),
+            MessageType::Encrypted => write!(f, "encrypted"),
+            MessageType::Moderation => write!(f, "moderation"),
+            MessageType::Reply => write!(f, "reply"),
+            MessageType::Edit => write!(f, "edit"),
+            MessageType::Delete => write!(f, "delete"),
+            MessageType::Reaction => write!(f, "reaction"),
+            MessageType::Presence => write!(f, "presence"),
         }
     }
 }
@@ -100,6 +161,44 @@ impl DirectMessage {
         Self::new(display_name, MessageType::ContactAccepted)
     }
 
+    pub fn group_invite(payload: &GroupInvitePayload) -> Self {
+        let content = serde_json::to_string(payload).expect("invite serialization is infallible");
+        Self::new(&content, MessageType::GroupInvite)
+    }
+
+    pub fn group_key(payload: &e2e::GroupKeyPayload) -> Self {
+        let content = serde_json::to_string(payload).expect("key serialization is infallible");
+        Self::new(&content, MessageType::GroupKey)
+    }
+
+    pub fn reply(target_id: &str, text: &str) -> Self {
+        Self::new(&ReferencePayload::json(target_id, text), MessageType::Reply)
+    }
+
+    pub fn edit(target_id: &str, text: &str) -> Self {
+        Self::new(&ReferencePayload::json(target_id, text), MessageType::Edit)
+    }
+
+    pub fn delete(target_id: &str) -> Self {
+        Self::new(&ReferencePayload::json(target_id, ""), MessageType::Delete)
+    }
+
+    pub fn reaction(target_id: &str, emoji: &str) -> Self {
+        Self::new(
+            &ReferencePayload::json(target_id, emoji),
+            MessageType::Reaction,
+        )
+    }
+
+    pub fn document(payload: &DocumentPayload) -> Self {
+        Self::new(&payload.to_json(), MessageType::Document)
+    }
+
+    /// Typing notice for a peer that supports presence.
+    pub fn presence(payload: &PresencePayload) -> Self {
+        Self::new(&payload.to_json(), MessageType::Presence)
+    }
+
     pub fn read_receipt(message_ids: &[&str]) -> Self {
         Self {
             id: format!("rr-{}", current_timestamp_ms()),
@@ -112,7 +211,7 @@ impl DirectMessage {
 
 /// Group Message (PubSub)
 /// Topic: six7-groups:{groupId}
-/// Note: Sender identity is authenticated by Korium transport layer
+/// Note: Published with a sender signature trailer (see [`signing::encode_signed`])
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GroupMessage {
@@ -142,6 +241,71 @@ impl GroupMessage {
     pub fn text(content: &str, group_id: &str) -> Self {
         Self::new(content, MessageType::Text, group_id)
     }
+
+    /// Announce our signed invite to the members of a private room.
+    pub fn group_join(invite: &membership::RoomInvite) -> Self {
+        let content = serde_json::to_string(invite).expect("invite serialization is infallible");
+        Self::new(&content, MessageType::GroupJoin, &invite.group_id)
+    }
+
+    /// Tell the members of a private room that we are leaving it.
+    pub fn group_leave(group_id: &str) -> Self {
+        Self::new("", MessageType::GroupLeave, group_id)
+    }
+
+    /// Reply to `target_id` within the thread started by `thread_id`.
+    pub fn reply(target_id: &str, thread_id: &str, text: &str, group_id: &str) -> Self {
+        Self::new(
+            &ReferencePayload::thread_json(target_id, Some(thread_id), text),
+            MessageType::Reply,
+            group_id,
+        )
+    }
+
+    pub fn edit(target_id: &str, text: &str, group_id: &str) -> Self {
+        Self::new(
+            &ReferencePayload::json(target_id, text),
+            MessageType::Edit,
+            group_id,
+        )
+    }
+
+    pub fn delete(target_id: &str, group_id: &str) -> Self {
+        Self::new(
+            &ReferencePayload::json(target_id, ""),
+            MessageType::Delete,
+            group_id,
+        )
+    }
+
+    pub fn reaction(target_id: &str, emoji: &str, group_id: &str) -> Self {
+        Self::new(
+            &ReferencePayload::json(target_id, emoji),
+            MessageType::Reaction,
+            group_id,
+        )
+    }
+
+    pub fn document(payload: &DocumentPayload, group_id: &str) -> Self {
+        Self::new(&payload.to_json(), MessageType::Document, group_id)
+    }
+
+    /// Our presence in a room, published on its presence topic.
+    pub fn presence(payload: &PresencePayload, group_id: &str) -> Self {
+        Self::new(&payload.to_json(), MessageType::Presence, group_id)
+    }
+
+    /// Publish a signed moderation action to its room.
+    pub fn moderation(action: &ModAction) -> Self {
+        let content = serde_json::to_string(action).expect("action serialization is infallible");
+        Self::new(&content, MessageType::Moderation, &action.group_id)
+    }
+
+    /// Wrap `inner` in an `encrypted` message sealed with `key`.
+    pub fn encrypted(key: &e2e::GroupKey, inner: &GroupMessage) -> Result<Self> {
+        let content = e2e::seal(key, &inner.group_id, inner)?;
+        Ok(Self::new(&content, MessageType::Encrypted, &inner.group_id))
+    }
 }
 
 /// Group Invite metadata (embedded in DirectMessage content as JSON string)
@@ -155,6 +319,50 @@ pub struct GroupInvitePayload {
     pub member_names: HashMap<String, String>,
     pub creator_id: String,
     pub created_at_ms: i64,
+    /// Identity the signed invite is bound to (private rooms only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invitee_id: Option<String>,
+    /// Unix epoch milliseconds after which the invite is void
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<i64>,
+    /// Creator's Ed25519 signature over the invite (hex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Current group key (hex) for end-to-end encrypted rooms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_epoch: Option<u32>,
+}
+
+/// Reference to an earlier message (embedded in `reply`, `edit`, `delete`
+/// and `reaction` message content as JSON string)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferencePayload {
+    /// Id of the message replied to, edited, deleted or reacted to
+    pub target_id: String,
+    /// Reply or replacement text, or the reaction emoji (empty for deletions)
+    #[serde(default)]
+    pub text: String,
+    /// Root of the thread a room reply belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+}
+
+impl ReferencePayload {
+    fn json(target_id: &str, text: &str) -> String {
+        Self::thread_json(target_id, None, text)
+    }
+
+    fn thread_json(target_id: &str, thread_id: Option<&str>, text: &str) -> String {
+        serde_json::to_string(&Self {
+            target_id: target_id.to_string(),
+            text: text.to_string(),
+            thread_id: thread_id.map(str::to_string),
+        })
+        .expect("reference serialization is infallible")
+    }
 }
 
 /// ACK Response for direct messages
@@ -168,11 +376,40 @@ impl AckResponse {
         Self { ack: true }
     }
 
+    pub fn rejected() -> Self {
+        Self { ack: false }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         postcard::to_allocvec(self).expect("AckResponse serialization is infallible")
     }
 }
 
+/// How a peer answered a direct message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckStatus {
+    /// Peer returned `AckResponse { ack: true }`
+    Acked,
+    /// Peer returned the pre-1.1 plain-text `received` response
+    LegacyAcked,
+    /// Peer answered, but not with a recognizable acknowledgment
+    NotAcked,
+}
+
+impl AckStatus {
+    pub fn from_response(response: &[u8]) -> Self {
+        match postcard::from_bytes::<AckResponse>(response) {
+            Ok(a) if a.ack => AckStatus::Acked,
+            _ if response == b"received" => AckStatus::LegacyAcked,
+            _ => AckStatus::NotAcked,
+        }
+    }
+
+    pub fn is_acked(self) -> bool {
+        self != AckStatus::NotAcked
+    }
+}
+
 /// Vibe payload types for anonymous matching
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -192,11 +429,18 @@ pub const MAX_MESSAGE_SIZE_BYTES: usize = 65536;
 pub const MAX_TOPIC_LENGTH: usize = 256;
 pub const MAX_IDENTITY_LENGTH: usize = 64;
 pub const GROUP_ID_LENGTH: usize = 36;
+pub const MAX_REACTION_BYTES: usize = 32;
+/// Most recent mentions listed by `/mentions`.
+const MAX_MENTIONS_SHOWN: usize = 20;
 
-fn sanitize_text(text: &str) -> String {
-    text.chars()
-        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
-        .collect()
+/// How long to wait for a direct message response before giving up.
+pub const DM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A reaction is a single short token such as an emoji.
+fn valid_reaction(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.len() <= MAX_REACTION_BYTES
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control())
 }
 
 fn current_timestamp_ms() -> i64 {
@@ -213,6 +457,76 @@ fn random_hex_id() -> String {
     hex::encode(bytes)
 }
 
+fn validate_identity(identity: &str) -> Result<()> {
+    if identity.len() != MAX_IDENTITY_LENGTH || hex::decode(identity).is_err() {
+        anyhow::bail!(
+            "Invalid identity. Must be {} hex characters.",
+            MAX_IDENTITY_LENGTH
+        );
+    }
+    Ok(())
+}
+
+/// Room names end up in topics, prompts and labels, so they may not hide
+/// anything the user cannot see.
+fn validate_room(room: &str) -> Result<()> {
+    if room.trim().is_empty() || presence::topic(room).len() > MAX_TOPIC_LENGTH {
+        anyhow::bail!(
+            "Room names must be 1 to {} bytes",
+            MAX_TOPIC_LENGTH - presence::TOPIC_PREFIX.len()
+        );
+    }
+    if sanitize::topic(room) != room {
+        anyhow::bail!("Room name contains control or invisible characters");
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Delivery
+// ============================================================================
+
+/// Why a direct message could not be delivered.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// Korium rejected or failed the request
+    Failed(String),
+    /// No response within [`DM_TIMEOUT`]
+    Timeout,
+    /// The encoded message has this many bytes, more than peers accept
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::Failed(e) => write!(f, "Failed to send: {e}"),
+            DeliveryError::Timeout => write!(f, "Timed out waiting for the peer to answer"),
+            DeliveryError::TooLarge(size) => write!(
+                f,
+                "Message too large once encoded ({size} bytes, max {MAX_MESSAGE_SIZE_BYTES})"
+            ),
+        }
+    }
+}
+
+/// Send a direct message and classify the peer's response.
+async fn send_direct(
+    node: &Node,
+    peer_identity: &str,
+    dm: &DirectMessage,
+) -> std::result::Result<AckStatus, DeliveryError> {
+    let payload = postcard::to_allocvec(dm).expect("Failed to serialize message");
+    if payload.len() > MAX_MESSAGE_SIZE_BYTES {
+        return Err(DeliveryError::TooLarge(payload.len()));
+    }
+    match tokio::time::timeout(DM_TIMEOUT, node.send(peer_identity, payload)).await {
+        Ok(Ok(response)) => Ok(AckStatus::from_response(&response)),
+        Ok(Err(e)) => Err(DeliveryError::Failed(e.to_string())),
+        Err(_) => Err(DeliveryError::Timeout),
+    }
+}
+
 // ============================================================================
 // CLI
 // ============================================================================
@@ -228,36 +542,142 @@ fn random_hex_id() -> String {
                         Protocol Version 1.3 — Compatible with the Six7 mobile app."
 )]
 struct Args {
-    /// Display name in the chatroom
-    #[arg(short, long, default_value = "anon")]
-    name: String,
+    /// Display name in the chatroom [default: anon]
+    #[arg(short, long, global = true)]
+    name: Option<String>,
 
-    /// Chatroom to join
-    #[arg(short, long, default_value = "lobby")]
-    room: String,
+    /// Chatroom to join [default: lobby]
+    #[arg(short, long, global = true)]
+    room: Option<String>,
 
-    /// Port to bind to (0 = random)
-    #[arg(short, long, default_value = "0")]
-    port: u16,
+    /// Port to bind to (0 = random) [default: 0]
+    #[arg(short, long, global = true)]
+    port: Option<u16>,
 
-    /// Join a specific peer: `<address>/<identity_hex>`
-    #[arg(short = 'j', long = "join")]
-    join: Option<String>,
+    /// Join a peer: `<address>/<identity_hex>`, or `@<file>` (`@-` for
+    /// stdin) with one per line. Repeatable; targets are joined concurrently
+    #[arg(short = 'j', long = "join", global = true)]
+    join: Vec<String>,
 
     /// Bootstrap from public Korium network
-    #[arg(short = 'B', long = "bootstrap")]
+    #[arg(short = 'B', long = "bootstrap", global = true)]
     bootstrap: bool,
 
     /// Enable debug logging
-    #[arg(short = 'd', long = "debug")]
+    #[arg(
+        short = 'd',
+        long = "debug",
+        global = true,
+        overrides_with = "no_debug"
+    )]
     debug: bool,
+
+    /// Disable debug logging even if the profile enables it
+    #[arg(long, global = true)]
+    no_debug: bool,
+
+    /// Config file [default: ~/.config/six7/config.toml, or $SIX7_CONFIG]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Named profile from the config file
+    #[arg(short = 'P', long, global = true)]
+    profile: Option<String>,
+
+    /// Identity file; created on first use so the identity survives restarts
+    #[arg(long, global = true)]
+    identity: Option<PathBuf>,
+
+    /// Terminal output style [default: color]
+    #[arg(long, value_enum, global = true)]
+    output: Option<OutputMode>,
+
+    /// Share online/away status and typing with peers that support it
+    #[arg(long, global = true, overrides_with = "no_presence")]
+    presence: bool,
+
+    /// Do not share presence even if the profile enables it
+    #[arg(long, global = true)]
+    no_presence: bool,
+
+    /// Serve Prometheus metrics over HTTP at this address, e.g. 127.0.0.1:9187
+    #[arg(long, global = true)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Non-interactive subcommands: join, perform one action, exit.
+///
+/// Exit codes are listed in [`oneshot`]; without a subcommand six7 starts
+/// the interactive chatroom.
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Send one direct message and wait for the peer's ACK
+    SendDm {
+        /// Recipient identity (64 hex characters)
+        #[arg(long)]
+        to: String,
+
+        /// Message text (read from stdin when omitted)
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// Publish one message to the room
+    Publish {
+        /// Message text (read from stdin when omitted)
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Seconds to wait for room peers before publishing
+        #[arg(long, default_value = "5")]
+        settle: u64,
+    },
+
+    /// Print room messages until `count` have arrived
+    Listen {
+        /// Number of messages to receive before exiting
+        #[arg(short, long, default_value = "1")]
+        count: usize,
+
+        /// Give up after this many seconds (0 = wait forever)
+        #[arg(short, long, default_value = "0")]
+        timeout: u64,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ConfigAction {
+    /// Print the effective configuration (file, profile and flags merged)
+    Show,
 }
 
+/// Display names of peers we have seen, keyed by full identity.
 type PeerRegistry = Arc<RwLock<HashMap<String, String>>>;
 
+type SharedContacts = Arc<RwLock<Contacts>>;
+
+type SharedBlocklist = Arc<RwLock<Blocklist>>;
+
+/// Rooms this node is subscribed to, in join order.
+type JoinedRooms = Arc<RwLock<Vec<String>>>;
+
 /// Parse a bootstrap string using Korium's own parser, with manual fallback
-/// for the `addr/identity` format used in the CLI banner.
+/// for the `addr/identity` format used in the CLI banner. `SIX7-...` invite
+/// codes are decoded (and checked for expiry) first.
 fn parse_bootstrap(s: &str) -> Result<(String, String)> {
+    if invite::is_invite_code(s) {
+        let invite = invite::Invite::decode(s)?;
+        return Ok((invite.identity, invite.addr));
+    }
     if let Some((identity, addr)) = Node::parse_bootstrap_txt(s) {
         return Ok((identity, addr));
     }
@@ -274,12 +694,61 @@ fn parse_bootstrap(s: &str) -> Result<(String, String)> {
     Ok((identity, addr))
 }
 
-fn print_banner(args: &Args, display_addr: &str, identity: &str) {
+/// Decode a room payload into `(sender_name, display_line)`.
+///
+/// `known_name` is the name already in the peer registry for `id_prefix`;
+/// legacy plain-text payloads carry their own `name@prefix: ` header.
+fn decode_room_message(
+    data: &[u8],
+    id_prefix: &str,
+    known_name: Option<String>,
+) -> (String, String) {
+    match postcard::from_bytes::<GroupMessage>(data) {
+        Ok(group_msg) => {
+            let name = known_name.unwrap_or_else(|| id_prefix.to_string());
+            (
+                name.clone(),
+                format!("{}@{}: {}", name, id_prefix, group_msg.content),
+            )
+        }
+        Err(_) => {
+            // Legacy plain-text fallback
+            let text = String::from_utf8_lossy(data);
+            let sender_name = text
+                .split_once(": ")
+                .and_then(|(prefix, _)| prefix.split_once('@'))
+                .map(|(name, _)| sanitize::name(name))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| id_prefix.to_string());
+            (sender_name, text.to_string())
+        }
+    }
+}
+
+/// Prefix for room lines; only names the room when several are joined.
+fn room_label(room: &str, multi_room: bool) -> String {
+    if multi_room {
+        format!("[{room}]")
+    } else {
+        "[room]".to_string()
+    }
+}
+
+/// Prompt shown while a DM conversation is open with `/query`.
+fn print_query_prompt(label: &str) {
+    print!("{} ", ui::paint(ui::MAGENTA, format!("[dm {label}]>")));
+    let _ = std::io::stdout().flush();
+}
+
+fn print_banner(settings: &Settings, display_addr: &str, identity: &str) {
     println!();
     println!("six7");
     println!();
-    println!("Nickname : {}", args.name);
-    println!("Room     : {}", args.room);
+    if let Some(ref profile) = settings.profile {
+        println!("Profile  : {}", profile);
+    }
+    println!("Nickname : {}", settings.name);
+    println!("Room     : {}", settings.rooms.join(", "));
     println!("Address  : {}", display_addr);
     println!();
     println!("Your Identity (for DMs):");
@@ -287,6 +756,13 @@ fn print_banner(args: &Args, display_addr: &str, identity: &str) {
     println!();
     println!("Bootstrap string (copy this line):");
     println!("{}/{}", display_addr, identity);
+    if let Ok(code) = invite::Invite::new(display_addr, identity, Some(settings.room()), None)
+        .and_then(|invite| invite.encode())
+    {
+        println!();
+        println!("Invite code (/invite-code for a QR code):");
+        println!("{}", code);
+    }
 }
 
 fn print_help() {
@@ -294,7 +770,48 @@ fn print_help() {
     println!("Commands:");
     println!("  /dm <identity> <message>  - Send direct message");
     println!("  /contact <identity>       - Send contact request");
+    println!("  /room <name>              - Switch the active room (joining it if needed)");
+    println!("  /join-peer <bootstrap>... - Join more peers (`addr/identity` or @file)");
+    println!("  /bootstrap                - Bootstrap from the public Korium network");
+    println!("  /status                   - Show network connectivity");
+    println!("  /invite-code [ttl]        - Show an invite code + QR for the active room");
+    println!("  /invite <identity> [ttl]  - Send a signed invite, making the active room private");
+    println!("  /accept [room]            - Accept a received private room invite (or list them)");
+    println!("  /members                  - List verified members of the active private room");
+    println!("  /rekey                    - Rotate the active private room's group key (creator)");
+    println!(
+        "  /mods                     - Show the active private room's owner, moderators and log"
+    );
+    println!("  /kick <peer> [reason]     - Remove a member and revoke their invite (moderators)");
+    println!("  /ban <peer> [reason]      - Keep a member out for good; /unban lifts it");
+    println!(
+        "  /mute member <peer> [ttl] - Hide a member's messages for every member (default 1h)"
+    );
+    println!("  /promote <peer>           - Make a member a moderator (owner); /demote undoes");
+    println!("  /leave [room]             - Leave a room (default: the active one)");
+    println!("  /paste [peer]             - Compose a multiline message (end with a lone \".\")");
+    println!("  /history [n]              - Show the last n messages of the active room");
+    println!("  /reply <msg> <text>       - Reply to a message (id prefix from /history)");
+    println!("  /edit <msg|last> <text>   - Edit one of your messages");
+    println!("  /delete <msg|last>        - Delete one of your messages");
+    println!("  /save <msg>               - Save a received document to the downloads directory");
+    println!("  /react [peer] <msg> <emoji> - React to a message (msg: id, ^N or last)");
+    println!("  /thread <msg> | close     - Open a message's thread, or return to the room");
+    println!("  /mentions                 - List recent messages that mention you");
+    println!("  /unread                   - Show unread counts per room and DM");
+    println!("  /read <peer>              - Show unread DMs from a peer and mark them read");
+    println!("  /query <peer>             - Open a DM conversation: plain lines go to the peer");
+    println!("  /close                    - Close the DM conversation and return to the room");
     println!("  /peers                    - List peers discovered via room messages");
+    println!("  /who                      - List peers currently in the active room's mesh");
+    println!("  /away [message] | /back   - Set your presence (needs --presence)");
+    println!("  /verify <peer> [confirm]  - Show the safety number; `confirm` marks verified");
+    println!("  /block <peer>             - Reject a peer's DMs and hide their room messages");
+    println!("  /unblock <peer>           - Lift a block");
+    println!("  /mute room [name]         - Hide a room's messages (/unmute room undoes)");
+    println!("  /mute peer <peer>         - Hide a peer's room messages and DM alerts");
+    println!("  /blocked                  - List blocked and muted peers and rooms");
+    println!("  /unverify <peer>          - Withdraw verification of a contact");
     println!(
         "  /list                     - Show all peer tables (fabric/transport/routing/gossipsub/dht)"
     );
@@ -302,183 +819,1795 @@ fn print_help() {
     println!("  /help                     - Show this help");
     println!("  /quit                     - Exit");
     println!();
-    println!("Anything else is broadcast to the room (Protocol v1.3).");
+    println!("Anything else is broadcast to the active room (Protocol v1.3), or sent to");
+    println!("the peer of an open /query conversation. /reply, /edit, /delete, /react and");
+    println!("/history then act on that conversation.");
     println!();
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+/// Publish our signed invite to `room` if it is private and an
+/// announcement is due.
+async fn announce_membership(
+    node: &Node,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    room: &str,
+) {
+    let Some(invite) = private_rooms.write().await.take_announcement(room) else {
+        return;
+    };
+    let payload = signing::encode_signed(signer, &GroupMessage::group_join(&invite));
+    if let Err(e) = node.publish(&room_topic(room), payload).await {
+        tracing::warn!("failed to announce membership in {room}: {e}");
+    }
+}
 
-    // Initialize logging
-    let log_level = if args.debug { "debug" } else { "warn" };
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
-        )
-        .init();
+/// Check a `groupJoin` announcement and admit `sender` to a private room.
+/// Returns `Ok(true)` when the sender is newly admitted; our own invite is
+/// then re-announced so members who arrived later learn about us too.
+async fn handle_group_join(
+    node: &Node,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    moderation: &SharedModerationLog,
+    room: &str,
+    sender: &str,
+    content: &str,
+) -> Result<bool> {
+    let invite: RoomInvite = serde_json::from_str(content).context("Malformed invite")?;
+    let Ok(owner) = room_owner(private_rooms, room).await else {
+        return Ok(false);
+    };
+    let roles = moderation.read().await.roles(room, &owner);
+    if roles.banned.contains(sender) {
+        anyhow::bail!("Peer is banned from this room");
+    }
+    if roles.revoked_invites.contains(&invite.signature) {
+        anyhow::bail!("Invite was revoked when the peer was kicked");
+    }
+    let admitted = {
+        let mut rooms = private_rooms.write().await;
+        if !rooms.is_private(room) {
+            return Ok(false);
+        }
+        let admitted = rooms.admit(room, sender, &invite)?;
+        if admitted {
+            rooms.save()?;
+        }
+        admitted
+    };
+    if admitted {
+        announce_membership(node, signer, private_rooms, room).await;
+    }
+    Ok(admitted)
+}
 
-    // Build node (includes PoW identity mining)
-    print!("Mining identity (PoW)... ");
-    std::io::Write::flush(&mut std::io::stdout()).ok();
+/// Handle a `groupInvite` DM from `from`. Signed invites addressed to us
+/// are verified and held until `/accept`; returns the room name and
+/// whether such an invite is now pending.
+async fn accept_group_invite(
+    private_rooms: &SharedPrivateRooms,
+    joined_rooms: &JoinedRooms,
+    public_rooms: &[String],
+    from: &str,
+    my_identity: &str,
+    content: &str,
+) -> Result<(String, bool)> {
+    let payload: GroupInvitePayload =
+        serde_json::from_str(content).context("Malformed group invite")?;
+    validate_room(&payload.group_id)?;
+    let Some(invite) = RoomInvite::from_payload(&payload) else {
+        return Ok((payload.group_id, false));
+    };
+    check_invitable(private_rooms, joined_rooms, public_rooms, &payload.group_id).await?;
+    let key = match (payload.group_key, payload.key_epoch) {
+        (Some(key), Some(epoch)) => Some(e2e::GroupKey { epoch, key }),
+        _ => None,
+    };
+    private_rooms
+        .write()
+        .await
+        .offer_invite(invite, key, from, my_identity)?;
+    Ok((payload.group_id, true))
+}
 
-    let node = Arc::new(Node::builder().bind_port(args.port).build().await?);
+/// An invite may only make a room private that we are not already in as a
+/// public room and that is not one of our configured public rooms.
+async fn check_invitable(
+    private_rooms: &SharedPrivateRooms,
+    joined_rooms: &JoinedRooms,
+    public_rooms: &[String],
+    room: &str,
+) -> Result<()> {
+    if private_rooms.read().await.is_private(room) {
+        return Ok(());
+    }
+    if public_rooms.iter().any(|r| r == room) || joined_rooms.read().await.iter().any(|r| r == room)
+    {
+        anyhow::bail!("{room} is a public room you are in; an invite cannot make it private");
+    }
+    Ok(())
+}
 
-    println!("done!");
+/// Handle a `groupKey` DM: install a rotated key sent by the room creator.
+/// Returns `(room, epoch)`.
+async fn accept_group_key(
+    private_rooms: &SharedPrivateRooms,
+    from: &str,
+    content: &str,
+) -> Result<(String, u32)> {
+    let payload: e2e::GroupKeyPayload =
+        serde_json::from_str(content).context("Malformed group key")?;
+    let mut rooms = private_rooms.write().await;
+    match rooms.get(&payload.group_id) {
+        Some(room) if room.creator_id == from => {}
+        Some(_) => anyhow::bail!("Key for {} was not sent by its creator", payload.group_id),
+        None => anyhow::bail!("Key for unknown room {}", payload.group_id),
+    }
+    rooms.install_key(
+        &payload.group_id,
+        e2e::GroupKey {
+            epoch: payload.key_epoch,
+            key: payload.group_key,
+        },
+    )?;
+    rooms.save()?;
+    Ok((payload.group_id, payload.key_epoch))
+}
 
-    let local_addr = node.local_addr()?;
-    let routable_addrs = node.routable_addresses();
-    let display_addr = routable_addrs
-        .first()
-        .map_or_else(|| local_addr.to_string(), |a| a.clone());
-    let identity = node.identity();
+/// Start a new key epoch for a room we created and send the key to every
+/// other admitted member. Returns `(epoch, members reached, members)`.
+async fn rotate_group_key(
+    node: &Node,
+    private_rooms: &SharedPrivateRooms,
+    room: &str,
+    my_identity: &str,
+) -> Result<(u32, usize, usize)> {
+    let (key, members) = {
+        let mut rooms = private_rooms.write().await;
+        match rooms.get(room) {
+            Some(r) if r.creator_id == my_identity => {}
+            _ => anyhow::bail!("Only the creator of {room} can rotate its key"),
+        }
+        let key = rooms.rotate_key(room)?;
+        rooms.save()?;
+        (key, rooms.members(room))
+    };
+    let dm = DirectMessage::group_key(&e2e::GroupKeyPayload {
+        group_id: room.to_string(),
+        key_epoch: key.epoch,
+        group_key: key.key.clone(),
+    });
+    let recipients: Vec<&String> = members.iter().filter(|m| *m != my_identity).collect();
+    let mut reached = 0;
+    for member in &recipients {
+        if matches!(send_direct(node, member, &dm).await, Ok(s) if s.is_acked()) {
+            reached += 1;
+        }
+    }
+    Ok((key.epoch, reached, recipients.len()))
+}
 
-    let peers: PeerRegistry = Arc::new(RwLock::new(HashMap::new()));
+/// Rotate a room's key in the background and report the outcome, so a
+/// departed or removed member cannot read what follows.
+fn spawn_key_rotation(
+    node: Arc<Node>,
+    private_rooms: SharedPrivateRooms,
+    room: String,
+    my_identity: String,
+) {
+    tokio::spawn(async move {
+        match rotate_group_key(&node, &private_rooms, &room, &my_identity).await {
+            Ok((epoch, reached, total)) => println!(
+                "{} {} key rotated to epoch {} ({}/{} members reached)",
+                ui::paint(ui::GREEN, "[room]"),
+                room,
+                epoch,
+                reached,
+                total
+            ),
+            Err(e) => eprintln!("Key rotation for {room} failed: {e:#}"),
+        }
+    });
+}
 
-    print_banner(&args, &display_addr, &identity);
+/// Owner of a private room, the authority for its moderation.
+async fn room_owner(private_rooms: &SharedPrivateRooms, room: &str) -> Result<String> {
+    private_rooms
+        .read()
+        .await
+        .get(room)
+        .map(|r| r.creator_id.clone())
+        .with_context(|| format!("{room} is a public room; moderation needs a private room"))
+}
 
-    // Bootstrap
-    if let Some(ref join_str) = args.join {
-        let (peer_identity, addr) = parse_bootstrap(join_str)?;
-        println!("\nJoining peer at {addr}...");
-        match node.join(&peer_identity, &[addr]).await {
-            Ok(()) => println!("Joined successfully!"),
-            Err(e) => eprintln!("Join failed: {e}"),
-        }
-    } else if args.bootstrap {
-        println!("\nBootstrapping from public Korium network...");
-        match node.bootstrap().await {
-            Ok(()) => println!("Bootstrap successful!"),
-            Err(e) => eprintln!("Bootstrap failed: {e}"),
+/// Check a received `moderation` message and add it to the log. Returns
+/// the action when it is new.
+async fn handle_moderation(
+    private_rooms: &SharedPrivateRooms,
+    moderation: &SharedModerationLog,
+    room: &str,
+    content: &str,
+) -> Result<Option<ModAction>> {
+    let action: ModAction = serde_json::from_str(content).context("Malformed action")?;
+    if action.group_id != room {
+        anyhow::bail!("Action is for another room");
+    }
+    let owner = room_owner(private_rooms, room).await?;
+    let mut log = moderation.write().await;
+    if !log.apply(action.clone(), &owner)? {
+        return Ok(None);
+    }
+    log.save()?;
+    Ok(Some(action))
+}
+
+/// Issue, record and publish a moderation action in `room`.
+#[allow(clippy::too_many_arguments)]
+async fn moderate(
+    node: &Node,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    moderation: &SharedModerationLog,
+    room: &str,
+    kind: ModKind,
+    target: &str,
+    until_ms: Option<i64>,
+    reason: &str,
+) -> Result<ModAction> {
+    let owner = room_owner(private_rooms, room).await?;
+    let revoked_invite = match kind {
+        ModKind::Kick => private_rooms
+            .read()
+            .await
+            .admitted_invite(room, target)
+            .map(|invite| invite.signature.clone()),
+        _ => None,
+    };
+    let action = ModAction::issue(signer, room, kind, target, until_ms, revoked_invite, reason);
+    {
+        let mut log = moderation.write().await;
+        log.apply(action.clone(), &owner)?;
+        log.save()?;
+    }
+    let payload = signing::encode_signed(signer, &GroupMessage::moderation(&action));
+    node.publish(&room_topic(room), payload)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to publish action: {e}"))?;
+    Ok(action)
+}
+
+/// Carry out the local effects of an accepted action: kicked and banned
+/// members lose their admission, and the owner rotates the room key.
+async fn honor_moderation(
+    node: &Arc<Node>,
+    private_rooms: &SharedPrivateRooms,
+    action: &ModAction,
+    my_identity: &str,
+) {
+    if !matches!(action.kind, ModKind::Kick | ModKind::Ban) {
+        return;
+    }
+    let we_own = {
+        let mut rooms = private_rooms.write().await;
+        if rooms.remove_member(&action.group_id, &action.target) {
+            if let Err(e) = rooms.save() {
+                tracing::warn!("failed to save private rooms: {e:#}");
+            }
         }
-    } else {
-        println!("\nNo bootstrap peer specified. This node is the first in the network.");
-        println!("Others can connect using the bootstrap string above.");
+        rooms
+            .get(&action.group_id)
+            .is_some_and(|r| r.creator_id == my_identity)
+    };
+    if we_own {
+        spawn_key_rotation(
+            node.clone(),
+            private_rooms.clone(),
+            action.group_id.clone(),
+            my_identity.to_string(),
+        );
     }
+}
 
-    // Subscribe to room topic
-    let room_topic = format!("chat/{}", args.room);
-    node.subscribe(&room_topic).await?;
-    println!("\nSubscribed to room: {}", args.room);
+/// One line describing an action, e.g. `alice banned bob (spam)`.
+fn describe_action(peers: &HashMap<String, String>, action: &ModAction) -> String {
+    let name = |id: &str| {
+        peers
+            .get(id)
+            .map_or_else(|| id[..8.min(id.len())].to_string(), |n| sanitize::text(n))
+    };
+    let mut line = format!(
+        "{} {} {}",
+        name(&action.issuer),
+        action.kind,
+        name(&action.target)
+    );
+    if let Some(until) = action.until_ms {
+        let mins = (until - action.issued_at_ms).max(0) / 60_000;
+        line.push_str(&format!(" for {mins}m"));
+    }
+    if !action.reason.is_empty() {
+        line.push_str(&format!(" ({})", sanitize::text(&action.reason)));
+    }
+    line
+}
 
-    // Get message receivers
-    let mut pubsub_rx = node.messages().await?;
-    let mut dm_rx = node.incoming_requests().await?;
+/// Whether messages of this type are kept in conversation history.
+fn is_history_type(message_type: &str) -> bool {
+    [
+        MessageType::Text,
+        MessageType::Reply,
+        MessageType::Edit,
+        MessageType::Delete,
+        MessageType::Reaction,
+        MessageType::Document,
+    ]
+    .iter()
+    .any(|t| t.to_string() == message_type)
+}
 
-    let room_filter = args.room.clone();
-    let my_identity = identity.clone();
-    let peers_for_pubsub = peers.clone();
+/// First few words of a message, for quoting.
+fn snippet(text: &str) -> String {
+    const MAX_CHARS: usize = 40;
+    let mut short: String = text.chars().take(MAX_CHARS).collect();
+    if text.chars().count() > MAX_CHARS {
+        short.push('…');
+    }
+    short
+}
 
-    // PubSub handler
-    tokio::spawn(async move {
-        while let Some(msg) = pubsub_rx.recv().await {
-            if msg.data.len() > MAX_MESSAGE_SIZE_BYTES {
-                continue;
+/// Whether a received history message, the message it refers to and the
+/// thread it belongs to have well-formed ids (see [`history::valid_id`]). Malformed references are
+/// let through to be shown as such.
+fn valid_message_ids(message_type: &str, id: &str, content: &str) -> bool {
+    if !history::valid_id(id) {
+        return false;
+    }
+    match message_type {
+        "reply" | "edit" | "delete" | "reaction" => {
+            match serde_json::from_str::<ReferencePayload>(content) {
+                Ok(payload) => {
+                    history::valid_id(&payload.target_id)
+                        && payload.thread_id.as_deref().is_none_or(history::valid_id)
+                }
+                Err(_) => true,
             }
+        }
+        _ => true,
+    }
+}
 
-            if msg.topic != format!("chat/{room_filter}") {
-                continue;
+/// Apply a `text`, `reply`, `edit`, `delete` or `reaction` message to the history of
+/// conversation `key` and return the text to show after the sender.
+/// `entry` holds the message as received, with its raw content. Received
+/// documents are held in `documents` for `/save`.
+async fn apply_message(
+    history: &SharedHistory,
+    documents: &SharedInbox,
+    key: &str,
+    message_type: &str,
+    mut entry: history::Entry,
+) -> String {
+    let reference = || serde_json::from_str::<ReferencePayload>(&entry.content);
+    let mut history = history.write().await;
+    let result = match message_type {
+        "reply" => match reference() {
+            Ok(payload) => {
+                let parent = history.get(key, &payload.target_id);
+                let quote = match parent {
+                    Some(parent) if parent.deleted => "↪ [deleted message] ".to_string(),
+                    Some(parent) => {
+                        format!("↪ {}: \"{}\" ", parent.name, snippet(&parent.content))
+                    }
+                    None => format!("↪ [{}] ", history::short(&payload.target_id)),
+                };
+                // Replies to replies stay in the thread of the first message
+                let root = payload
+                    .thread_id
+                    .or_else(|| parent.and_then(|p| p.thread.clone()))
+                    .unwrap_or_else(|| payload.target_id.clone());
+                entry.reply_to = Some(payload.target_id);
+                entry.thread = Some(root.clone());
+                entry.content = payload.text;
+                let text = entry.content.clone();
+                history.record(key, entry).map(|()| {
+                    let replies = history.reply_counts(key).get(&root).copied().unwrap_or(0);
+                    format!(
+                        "{quote}{text}  [🧵 {} · {replies} {}]",
+                        history::short(&root),
+                        if replies == 1 { "reply" } else { "replies" }
+                    )
+                })
             }
-
-            let sender_id = &msg.from;
-            if sender_id == &my_identity {
-                continue;
+            Err(_) => Ok("[malformed reply]".to_string()),
+        },
+        "edit" => match reference() {
+            Ok(payload) => {
+                let short = history::short(&payload.target_id);
+                history
+                    .edit(key, &payload.target_id, &entry.from, &payload.text)
+                    .map(|change| match change {
+                        history::Change::NotAuthor => {
+                            "[ignored edit of another member's message]".to_string()
+                        }
+                        history::Change::Unknown => {
+                            format!("{} (edit of unseen message {short})", payload.text)
+                        }
+                        history::Change::Applied(_) => format!("{} (edited)", payload.text),
+                    })
             }
-
-            let id_prefix = &sender_id[..8.min(sender_id.len())];
-
-            let (sender_name, display_content) =
-                match postcard::from_bytes::<GroupMessage>(&msg.data) {
-                    Ok(group_msg) => {
-                        let name = {
-                            let peers = peers_for_pubsub.read().await;
-                            peers.get(id_prefix).cloned()
+            Err(_) => Ok("[malformed edit]".to_string()),
+        },
+        "delete" => match reference() {
+            Ok(payload) => {
+                let short = history::short(&payload.target_id);
+                history
+                    .delete(key, &payload.target_id, &entry.from)
+                    .map(|change| match change {
+                        history::Change::NotAuthor => {
+                            "[ignored deletion of another member's message]".to_string()
+                        }
+                        _ => format!("[deleted message {short}]"),
+                    })
+            }
+            Err(_) => Ok("[malformed delete]".to_string()),
+        },
+        "document" => match DocumentPayload::parse(&entry.content) {
+            Ok(payload) => {
+                let summary = payload.summary();
+                let (line, content) = match document::preview(&payload) {
+                    Ok(preview) => {
+                        let hint = format!("{summary}  [/save {} to keep it]", entry.short_id());
+                        documents.write().await.hold(&entry.id, payload);
+                        if preview.is_empty() {
+                            (hint, summary)
+                        } else {
+                            (
+                                format!("{hint}\n{preview}"),
+                                format!("{summary}\n{preview}"),
+                            )
                         }
-                        .unwrap_or_else(|| id_prefix.to_string());
-                        (
-                            name.clone(),
-                            format!("{}@{}: {}", name, id_prefix, group_msg.content),
-                        )
-                    }
-                    Err(_) => {
-                        // Legacy plain-text fallback
-                        let text = String::from_utf8_lossy(&msg.data);
-                        let sender_name = text
-                            .split_once(": ")
-                            .and_then(|(prefix, _)| prefix.split_once('@'))
-                            .map(|(name, _)| name.to_string())
-                            .unwrap_or_else(|| id_prefix.to_string());
-                        (sender_name, text.to_string())
                     }
+                    Err(e) => (format!("{summary} [unreadable: {e:#}]"), summary),
                 };
-
-            // Track peer
-            {
-                let mut peers = peers_for_pubsub.write().await;
-                if peers.len() > 1000 {
-                    peers.clear(); // Prevent unbounded growth
-                }
-                peers
-                    .entry(id_prefix.to_string())
-                    .or_insert_with(|| sender_name.clone());
+                entry.content = content;
+                history.record(key, entry).map(|()| line)
+            }
+            Err(_) => Ok("[malformed document]".to_string()),
+        },
+        "reaction" => match reference() {
+            Ok(payload) if valid_reaction(&payload.text) => {
+                let emoji = payload.text;
+                let short = history::short(&payload.target_id);
+                history
+                    .react(key, &payload.target_id, &entry.from, &emoji)
+                    .map(|change| match change {
+                        history::Change::Applied(target) => format!(
+                            "reacted {emoji} to \"{}\"  [{}]",
+                            snippet(&target.content),
+                            target.reaction_summary()
+                        ),
+                        _ => format!("reacted {emoji} to [{short}]"),
+                    })
             }
+            _ => Ok("[malformed reaction]".to_string()),
+        },
+        _ => {
+            let line = entry.content.clone();
+            history.record(key, entry).map(|()| line)
+        }
+    };
+    result.unwrap_or_else(|e| {
+        tracing::warn!("history not updated: {e:#}");
+        String::new()
+    })
+}
+
+/// Record a message we sent in `key`'s history and return the text to
+/// echo. Our own documents are stored by their summary, not saved again.
+async fn record_own(
+    history: &SharedHistory,
+    documents: &SharedInbox,
+    key: &str,
+    message_type: &str,
+    mut entry: history::Entry,
+) -> String {
+    if message_type != MessageType::Document.to_string() {
+        return apply_message(history, documents, key, message_type, entry).await;
+    }
+    let Ok(payload) = DocumentPayload::parse(&entry.content) else {
+        return String::new();
+    };
+    entry.content = payload.summary();
+    let summary = entry.content.clone();
+    if let Err(e) = history.write().await.record(key, entry) {
+        tracing::warn!("history not updated: {e:#}");
+    }
+    summary
+}
+
+/// A message in `key`'s history named by a user: `last` for our own
+/// latest message, `^N` for the Nth most recent one (`^1` is the latest),
+/// otherwise a prefix of its id.
+async fn resolve_message_ref(
+    history: &SharedHistory,
+    key: &str,
+    my_identity: &str,
+    reference: &str,
+) -> Result<history::Entry> {
+    let mut history = history.write().await;
+    if reference == "last" {
+        return history
+            .last_from(key, my_identity)
+            .context("You have not sent anything here yet");
+    }
+    if let Some(back) = reference.strip_prefix('^') {
+        let n: usize = back
+            .parse()
+            .with_context(|| format!("Invalid message reference {reference}"))?;
+        return history
+            .nth_latest(key, n)
+            .with_context(|| format!("No message {reference} in this conversation"));
+    }
+    history.find_prefix(key, reference)
+}
+
+/// One history entry for `/history`: short id, sender, text and markers.
+/// `replies` is the number of replies in the thread `entry` starts; with
+/// a `directory`, mentions in the text are highlighted.
+fn render_entry(
+    entry: &history::Entry,
+    replies: usize,
+    directory: Option<&mentions::Directory>,
+) -> String {
+    let reply = match entry.reply_to {
+        Some(ref parent) => format!("↪ {} ", history::short(parent)),
+        None => String::new(),
+    };
+    let mut text = if entry.deleted {
+        "[deleted]".to_string()
+    } else if entry.edited {
+        format!("{} (edited)", entry.content)
+    } else {
+        entry.content.clone()
+    };
+    if !entry.reactions.is_empty() {
+        text.push_str(&format!("  [{}]", entry.reaction_summary()));
+    }
+    if replies > 0 {
+        text.push_str(&format!("  [🧵 {replies}]"));
+    }
+    let text = sanitize::text(&text);
+    let body = match directory {
+        Some(directory) => markup::render_with(&text, &|plain| directory.highlight(plain)),
+        None => markup::render(&text),
+    };
+    format!(
+        "{}  {}: {}{}",
+        entry.short_id(),
+        sanitize::text(&entry.name),
+        reply,
+        body
+    )
+}
+
+/// Most messages printed when a conversation with unread messages is opened.
+const MAX_REPLAY: usize = 50;
 
-            println!("\x1b[32m[room]\x1b[0m {}", sanitize_text(&display_content));
+/// Mark conversation `key` read up to its latest message. Returns the ids
+/// of the messages from others that were unread.
+async fn mark_conversation_read(
+    history: &SharedHistory,
+    markers: &SharedReadMarkers,
+    key: &str,
+    my_identity: &str,
+) -> Vec<String> {
+    let mut history = history.write().await;
+    let entries = history.entries(key);
+    let mut markers = markers.write().await;
+    let unread = markers
+        .unread(key, entries, my_identity)
+        .into_iter()
+        .map(|e| e.id.clone())
+        .collect();
+    if markers.mark_read(key, entries) {
+        if let Err(e) = markers.save() {
+            tracing::warn!("failed to save read markers: {e:#}");
         }
-    });
+    }
+    unread
+}
 
-    // DM handler
-    tokio::spawn(async move {
-        while let Some((from, data, response_tx)) = dm_rx.recv().await {
-            if data.len() > MAX_MESSAGE_SIZE_BYTES {
-                continue;
+/// Print the last `tail` messages of conversation `key` plus everything
+/// unread (up to [`MAX_REPLAY`]), with a marker line where the unread
+/// messages start, then mark the conversation read. Returns the ids of
+/// the messages from others that were unread.
+async fn replay_conversation(
+    history: &SharedHistory,
+    markers: &SharedReadMarkers,
+    key: &str,
+    my_identity: &str,
+    tail: usize,
+) -> Vec<String> {
+    {
+        let mut history = history.write().await;
+        let reply_counts = history.reply_counts(key);
+        let entries = history.entries(key);
+        let markers = markers.read().await;
+        let first_unread = markers.first_unread(key, entries);
+        let unread = markers.unread(key, entries, my_identity).len();
+        let start = entries
+            .len()
+            .saturating_sub(tail)
+            .min(first_unread)
+            .max(entries.len().saturating_sub(MAX_REPLAY));
+        for (i, entry) in entries.iter().enumerate().skip(start) {
+            if unread > 0 && i == first_unread.max(start) {
+                println!("{}", unread_marker(unread));
+            }
+            let replies = reply_counts.get(&entry.id).copied().unwrap_or(0);
+            println!("  {}", render_entry(entry, replies, None));
+        }
+    }
+    mark_conversation_read(history, markers, key, my_identity).await
+}
+
+fn unread_marker(count: usize) -> String {
+    ui::paint(ui::YELLOW, format!("──── {count} unread ────"))
+}
+
+/// Tell `peer` we have read `ids`, without waiting for the answer.
+fn send_read_receipt(node: &Arc<Node>, peer: &str, ids: Vec<String>) {
+    if ids.is_empty() {
+        return;
+    }
+    let node = node.clone();
+    let peer = peer.to_string();
+    tokio::spawn(async move {
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        if let Err(e) = send_direct(&node, &peer, &DirectMessage::read_receipt(&ids)).await {
+            tracing::debug!("read receipt to {peer} failed: {e}");
+        }
+    });
+}
+
+/// Refuse a room payload that receivers would drop for its size.
+fn check_encoded_size(payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_MESSAGE_SIZE_BYTES {
+        anyhow::bail!(
+            "Message too large once encoded ({} bytes, max {MAX_MESSAGE_SIZE_BYTES})",
+            payload.len()
+        );
+    }
+    Ok(())
+}
+
+/// Publish `msg` to `room`, sealed with the room's group key if it has
+/// one. Returns the marker to show in front of the line.
+async fn publish_room_message(
+    node: &Node,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    room: &str,
+    msg: &GroupMessage,
+) -> Result<&'static str> {
+    let key = private_rooms.read().await.current_key(room);
+    let (msg, lock) = match key {
+        Some(ref key) => (GroupMessage::encrypted(key, msg)?, "🔒 "),
+        None => (msg.clone(), ""),
+    };
+    let payload = signing::encode_signed(signer, &msg);
+    check_encoded_size(&payload)?;
+    node.publish(&room_topic(room), payload)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to send message: {e}"))?;
+    Ok(lock)
+}
+
+/// Describe how a room's traffic is protected.
+fn encryption_note(private_rooms: &membership::PrivateRooms, room: &str) -> &'static str {
+    if private_rooms.current_key(room).is_some() {
+        "🔒 end-to-end encrypted"
+    } else {
+        "unencrypted: readable by any node in the topic mesh"
+    }
+}
+
+/// `/status`: connectivity summary and how each joined room is protected.
+async fn print_status(
+    node: &Node,
+    rooms: &[String],
+    active_room: &str,
+    private_rooms: &SharedPrivateRooms,
+) {
+    let all = node.all_contacts().await.len();
+    let connected = node.connected_contacts().await.len();
+    let state = if connected > 0 {
+        ui::paint(ui::GREEN, "connected")
+    } else {
+        ui::paint(ui::RED, "isolated")
+    };
+    println!("Network  : {state}");
+    println!("Peers    : {connected} connected, {all} known");
+    println!("Routing  : {} contacts", node.get_peers().await.len());
+    println!("Rooms    :");
+    let private_rooms = private_rooms.read().await;
+    for room in rooms {
+        let marker = if room == active_room { "*" } else { " " };
+        println!(
+            "  {marker} {room}  ({})",
+            encryption_note(&private_rooms, room)
+        );
+    }
+}
+
+/// Resolve a peer given as a full identity, a display name or an
+/// identity prefix from the peer registry.
+async fn resolve_peer(peers: &PeerRegistry, query: &str) -> Result<String> {
+    if validate_identity(query).is_ok() {
+        return Ok(query.to_lowercase());
+    }
+    let query_lower = query.to_lowercase();
+    let peers = peers.read().await;
+    let matches: Vec<&String> = peers
+        .iter()
+        .filter(|(id, name)| name.eq_ignore_ascii_case(query) || id.starts_with(&query_lower))
+        .map(|(id, _)| id)
+        .collect();
+    match matches[..] {
+        [id] => Ok(id.clone()),
+        [] => anyhow::bail!("Unknown peer {query}; use the full 64-hex identity"),
+        _ => anyhow::bail!("{query} matches {} peers; be more specific", matches.len()),
+    }
+}
+
+/// `name (abcd1234)` for a peer, or just the identity prefix if we do not
+/// know their name.
+async fn peer_label(peers: &PeerRegistry, identity: &str) -> String {
+    let short = &identity[..8.min(identity.len())];
+    match peers.read().await.get(identity) {
+        Some(name) => format!("{} ({short})", sanitize::text(name)),
+        None => short.to_string(),
+    }
+}
+
+/// Identities of the peers in `room`'s gossipsub mesh, eager and lazy.
+async fn room_peers(node: &Node, room: &str) -> std::collections::BTreeSet<String> {
+    let topic = room_topic(room);
+    node.gossipsub_topic_peers()
+        .await
+        .iter()
+        .filter(|tp| tp.topic == topic)
+        .flat_map(|tp| tp.eager_peers.iter().chain(tp.lazy_peers.iter()))
+        .map(|p| hex::encode(p.as_bytes()))
+        .collect()
+}
+
+/// Poll the mesh of every joined room and announce peers joining and
+/// leaving, except in muted rooms and for hidden peers.
+fn spawn_roster(
+    node: Arc<Node>,
+    joined_rooms: JoinedRooms,
+    peers: PeerRegistry,
+    blocklist: SharedBlocklist,
+) {
+    tokio::spawn(async move {
+        let mut roster = roster::Roster::new();
+        let mut interval = tokio::time::interval(roster::POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let rooms = joined_rooms.read().await.clone();
+            roster.retain(&rooms);
+            let multi_room = rooms.len() > 1;
+            for room in &rooms {
+                let changes = roster.update(room, &room_peers(&node, room).await);
+                let events: Vec<(&String, &str)> = {
+                    let blocklist = blocklist.read().await;
+                    if blocklist.is_room_muted(room) {
+                        continue;
+                    }
+                    changes
+                        .joined
+                        .iter()
+                        .map(|p| (p, "joined"))
+                        .chain(changes.left.iter().map(|p| (p, "left")))
+                        .filter(|(p, _)| !blocklist.hides_peer(p))
+                        .collect()
+                };
+                for (peer, event) in events {
+                    println!(
+                        "{} {} {event}",
+                        ui::paint(ui::GREEN, room_label(room, multi_room)),
+                        ui::paint(ui::CYAN, peer_label(&peers, peer).await)
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Publish `payload` on `room`'s presence topic. Private rooms get none.
+async fn publish_presence(
+    node: &Node,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    room: &str,
+    payload: &PresencePayload,
+) {
+    if private_rooms.read().await.is_private(room) {
+        return;
+    }
+    let msg = GroupMessage::presence(payload, room);
+    if let Err(e) = node
+        .publish(&presence::topic(room), signing::encode_signed(signer, &msg))
+        .await
+    {
+        tracing::debug!("presence for {room} not sent: {e}");
+    }
+}
+
+/// Announce our state in every joined room now and then once per
+/// heartbeat interval.
+fn spawn_presence(
+    node: Arc<Node>,
+    signer: signing::Signer,
+    joined_rooms: JoinedRooms,
+    private_rooms: SharedPrivateRooms,
+    presence: SharedPresence,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(presence::HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let rooms = joined_rooms.read().await.clone();
+            let payload = {
+                let mut presence = presence.write().await;
+                presence.prune(&rooms, Instant::now());
+                presence.payload()
+            };
+            for room in &rooms {
+                publish_presence(&node, &signer, &private_rooms, room, &payload).await;
+            }
+        }
+    });
+}
+
+/// Tell `peer`, or else `room`, that we are typing, unless we did so a
+/// moment ago. Peers that never showed presence support get nothing.
+async fn send_typing(
+    node: &Arc<Node>,
+    signer: &signing::Signer,
+    private_rooms: &SharedPrivateRooms,
+    presence: &SharedPresence,
+    room: &str,
+    peer: Option<&str>,
+) {
+    let payload = {
+        let mut presence = presence.write().await;
+        if peer.is_some_and(|peer| !presence.supports(peer)) {
+            return;
+        }
+        let Some(payload) = presence.typing(Instant::now()) else {
+            return;
+        };
+        payload
+    };
+    let Some(peer) = peer else {
+        publish_presence(node, signer, private_rooms, room, &payload).await;
+        return;
+    };
+    let node = node.clone();
+    let peer = peer.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = send_direct(&node, &peer, &DirectMessage::presence(&payload)).await {
+            tracing::debug!("typing notice to {peer} failed: {e}");
+        }
+    });
+}
+
+/// Handle a message on `room`'s presence topic and print what changed.
+#[allow(clippy::too_many_arguments)]
+async fn receive_presence(
+    presence: &SharedPresence,
+    private_rooms: &SharedPrivateRooms,
+    blocklist: &SharedBlocklist,
+    peers: &PeerRegistry,
+    joined_rooms: &[String],
+    room: &str,
+    sender: &str,
+    data: &[u8],
+) {
+    if !presence.read().await.enabled
+        || !joined_rooms.iter().any(|r| r == room)
+        || private_rooms.read().await.is_private(room)
+    {
+        return;
+    }
+    let Some((msg, Authenticity::Verified)) = signing::decode_signed(data, sender) else {
+        return;
+    };
+    if msg.message_type != MessageType::Presence.to_string() || msg.group_id != room {
+        return;
+    }
+    let Ok(payload) = PresencePayload::parse(&msg.content) else {
+        return;
+    };
+    let Some(notice) = presence
+        .write()
+        .await
+        .update(room, sender, &payload, Instant::now())
+    else {
+        return;
+    };
+    {
+        let blocklist = blocklist.read().await;
+        if blocklist.is_room_muted(room) || blocklist.hides_peer(sender) {
+            return;
+        }
+    }
+    println!(
+        "{} {} {notice}",
+        ui::paint(ui::GREEN, room_label(room, joined_rooms.len() > 1)),
+        ui::paint(ui::CYAN, peer_label(peers, sender).await)
+    );
+}
+
+/// Record the name `identity` goes by, warning if a verified contact
+/// already uses that name with a different identity.
+async fn learn_peer_name(
+    peers: &PeerRegistry,
+    contacts: &SharedContacts,
+    identity: &str,
+    name: &str,
+) {
+    let short = &identity[..8.min(identity.len())];
+    let name = sanitize::name(name);
+    if name.is_empty() {
+        return;
+    }
+    let name = name.as_str();
+    if name != short {
+        if let Some((verified_id, _)) = contacts.read().await.name_conflict(name, identity) {
+            println!(
+                "{}",
+                ui::paint(
+                    ui::RED,
+                    format!(
+                        "⚠ WARNING: {} is a verified contact with identity {}, \
+                         but {} now claims that name. This may be an impersonation attempt.",
+                        name,
+                        &verified_id[..8],
+                        short
+                    )
+                )
+            );
+        }
+    }
+    let mut peers = peers.write().await;
+    if peers.len() > 1000 {
+        peers.clear(); // Prevent unbounded growth
+    }
+    if name != short || !peers.contains_key(identity) {
+        peers.insert(identity.to_string(), name.to_string());
+    }
+}
+
+/// Room topic used on the Korium fabric.
+fn room_topic(room: &str) -> String {
+    format!("chat/{room}")
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let settings = match Settings::resolve(&args) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    ui::init(settings.output);
+    markup::init(settings.rich_text);
+    document::init(settings.state_file("downloads"));
+
+    // Initialize logging
+    let log_level = if settings.debug { "debug" } else { "warn" };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
+        )
+        .init();
+
+    let result = match args.command {
+        Some(Command::Config {
+            action: ConfigAction::Show,
+        }) => config::show(&settings).map(|()| ExitCode::SUCCESS),
+        Some(Command::SendDm { to, message }) => oneshot::send_dm(&settings, &to, message).await,
+        Some(Command::Publish { message, settle }) => {
+            oneshot::publish(&settings, message, settle).await
+        }
+        Some(Command::Listen { count, timeout }) => {
+            oneshot::listen(&settings, count, timeout).await
+        }
+        None => run_chat(settings).await.map(|()| ExitCode::SUCCESS),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Build the Korium node. Mines a fresh PoW identity unless one is stored
+/// in the configured identity file; a freshly mined identity is saved there.
+async fn start_node(settings: &Settings) -> Result<Arc<Node>> {
+    let mut builder = Node::builder().bind_port(settings.port);
+    let stored = match settings.identity_file {
+        Some(ref path) => identity::load(path)?,
+        None => None,
+    };
+    let mined = stored.is_none();
+    if let Some(keypair) = stored {
+        builder = builder.keypair(keypair);
+    }
+    let node = builder.build().await?;
+    if let (Some(ref path), true) = (&settings.identity_file, mined) {
+        identity::save(path, node.keypair())?;
+    }
+    Ok(Arc::new(node))
+}
+
+/// Whether [`start_node`] will load an identity instead of mining one.
+fn has_stored_identity(settings: &Settings) -> bool {
+    settings.identity_file.as_deref().is_some_and(Path::exists)
+}
+
+/// Bootstrap candidates: configured peers first, then saved known peers.
+fn bootstrap_candidates(settings: &Settings) -> Vec<String> {
+    let mut candidates = settings.bootstrap_peers.clone();
+    for peer in bootstrap::load_known_peers(&settings.known_peers_file) {
+        if !candidates.contains(&peer) {
+            candidates.push(peer);
+        }
+    }
+    candidates
+}
+
+/// Attach to the bootstrap candidates (joined concurrently, with retry and
+/// backoff), or the public network. `report` sees each per-target outcome.
+/// Returns `Ok(false)` when neither is configured.
+async fn bootstrap_node(
+    node: &Node,
+    settings: &Settings,
+    report: impl Fn(&bootstrap::JoinReport),
+) -> Result<bool> {
+    bootstrap::join_any(
+        node,
+        &bootstrap_candidates(settings),
+        settings.public_bootstrap,
+        report,
+    )
+    .await
+}
+
+async fn run_chat(settings: Settings) -> Result<()> {
+    if has_stored_identity(&settings) {
+        print!("Loading identity... ");
+    } else {
+        print!("Mining identity (PoW)... ");
+    }
+    std::io::Write::flush(&mut std::io::stdout()).ok();
+
+    let node = start_node(&settings).await?;
+
+    println!("done!");
+
+    let local_addr = node.local_addr()?;
+    let routable_addrs = node.routable_addresses();
+    let display_addr = routable_addrs
+        .first()
+        .map_or_else(|| local_addr.to_string(), |a| a.clone());
+    let identity = node.identity();
+    let signer = signing::Signer::from_node(&node);
+    let private_rooms: SharedPrivateRooms = Arc::new(RwLock::new(membership::PrivateRooms::load(
+        &settings.state_file("private_rooms.toml"),
+    )?));
+
+    let peers: PeerRegistry = Arc::new(RwLock::new(HashMap::new()));
+    let contacts: SharedContacts = Arc::new(RwLock::new(Contacts::load(
+        &settings.state_file("contacts.toml"),
+    )?));
+    let blocklist: SharedBlocklist = Arc::new(RwLock::new(Blocklist::load(
+        &settings.state_file("blocklist.toml"),
+    )?));
+    let history: SharedHistory = Arc::new(RwLock::new(history::History::new(
+        &settings.state_file("history"),
+    )));
+    let documents: SharedInbox = Arc::new(RwLock::new(document::Inbox::default()));
+    let moderation: SharedModerationLog = Arc::new(RwLock::new(moderation::ModerationLog::load(
+        &settings.state_file("moderation.toml"),
+    )?));
+    let read_markers: SharedReadMarkers = Arc::new(RwLock::new(unread::ReadMarkers::load(
+        &settings.state_file("read.toml"),
+    )?));
+    let stats: SharedStats = Arc::new(stats::Stats::default());
+    let presence: SharedPresence =
+        Arc::new(RwLock::new(presence::Presence::new(settings.presence)));
+
+    print_banner(&settings, &display_addr, &identity);
+    if let Some(addr) = settings.metrics_addr {
+        let bound = metrics::spawn(addr, node.clone(), stats.clone()).await?;
+        println!("\nMetrics at http://{bound}/metrics");
+    }
+
+    // Bootstrap
+    let candidates = bootstrap_candidates(&settings);
+    if let [ref join_str] = candidates[..] {
+        let (_, addr) = parse_bootstrap(join_str)?;
+        println!("\nJoining peer at {addr}...");
+    } else if !candidates.is_empty() {
+        println!("\nJoining {} bootstrap peers...", candidates.len());
+    } else if settings.public_bootstrap {
+        println!("\nBootstrapping from public Korium network...");
+    }
+    match bootstrap_node(&node, &settings, |r| println!("{r}")).await {
+        Ok(true) => println!("Joined successfully!"),
+        Ok(false) => {
+            println!("\nNo bootstrap peer specified. This node is the first in the network.");
+            println!("Others can connect using the bootstrap string above.");
+        }
+        Err(e) => eprintln!("{e}"),
+    }
+    bootstrap::spawn_watchdog(
+        node.clone(),
+        settings.bootstrap_peers.clone(),
+        settings.known_peers_file.clone(),
+        settings.public_bootstrap,
+    );
+
+    // Subscribe to room topics
+    let joined_rooms: JoinedRooms = Arc::new(RwLock::new(Vec::new()));
+    for room in &settings.rooms {
+        node.subscribe(&room_topic(room)).await?;
+        if settings.presence {
+            node.subscribe(&presence::topic(room)).await?;
+        }
+        joined_rooms.write().await.push(room.clone());
+        println!("\nSubscribed to room: {}", room);
+        announce_membership(&node, &signer, &private_rooms, room).await;
+    }
+    let mut active_room = settings.room().to_string();
+    // Thread opened with /thread: (room, root message id)
+    let mut active_thread: Option<(String, String)> = None;
+
+    // Get message receivers
+    let mut pubsub_rx = node.messages().await?;
+    let mut dm_rx = node.incoming_requests().await?;
+
+    let rooms_for_pubsub = joined_rooms.clone();
+    let my_identity = identity.clone();
+    let peers_for_pubsub = peers.clone();
+    let contacts_for_pubsub = contacts.clone();
+    let blocklist_for_pubsub = blocklist.clone();
+    let moderation_for_pubsub = moderation.clone();
+    let history_for_pubsub = history.clone();
+    let documents_for_pubsub = documents.clone();
+    let node_for_pubsub = node.clone();
+    let private_rooms_for_pubsub = private_rooms.clone();
+    let signer_for_pubsub = signer.clone();
+    let stats_for_pubsub = stats.clone();
+    let notify_for_pubsub = settings.notify.clone();
+    let presence_for_pubsub = presence.clone();
+    let my_name = settings.name.clone();
+
+    // PubSub handler
+    tokio::spawn(async move {
+        // (room, sender) pairs already told about hidden messages
+        let mut hidden_notified = std::collections::HashSet::new();
+        let mut limiter = RateLimiter::new(ratelimit::ROOM_RATE, ratelimit::ROOM_BURST);
+        let mut presence_limiter = RateLimiter::new(presence::RATE, presence::BURST);
+        while let Some(msg) = pubsub_rx.recv().await {
+            if let Some(room) = presence::room_of(&msg.topic) {
+                if msg.data.len() <= MAX_MESSAGE_SIZE_BYTES
+                    && msg.from != my_identity
+                    && presence_limiter.check(&msg.from, Instant::now()) == Verdict::Allow
+                {
+                    let rooms = rooms_for_pubsub.read().await.clone();
+                    receive_presence(
+                        &presence_for_pubsub,
+                        &private_rooms_for_pubsub,
+                        &blocklist_for_pubsub,
+                        &peers_for_pubsub,
+                        &rooms,
+                        room,
+                        &msg.from,
+                        &msg.data,
+                    )
+                    .await;
+                }
+                continue;
+            }
+
+            let (room, multi_room) = {
+                let rooms = rooms_for_pubsub.read().await;
+                let room = rooms.iter().find(|r| msg.topic == room_topic(r)).cloned();
+                (room, rooms.len() > 1)
+            };
+            let Some(room) = room else {
+                continue;
+            };
+
+            let sender_id = &msg.from;
+            if sender_id == &my_identity {
+                continue;
+            }
+            stats::bump(&stats_for_pubsub.messages_received);
+            if msg.data.len() > MAX_MESSAGE_SIZE_BYTES {
+                stats::bump(&stats_for_pubsub.messages_dropped);
+                continue;
+            }
+
+            let id_prefix = &sender_id[..8.min(sender_id.len())];
+            let label = ui::paint(ui::GREEN, room_label(&room, multi_room));
+
+            match limiter.check(sender_id, Instant::now()) {
+                Verdict::Allow => {}
+                Verdict::Throttled | Verdict::Muted => {
+                    stats::bump(&stats_for_pubsub.room_throttled);
+                    stats::bump(&stats_for_pubsub.messages_dropped);
+                    continue;
+                }
+                Verdict::AutoMuted => {
+                    stats::bump(&stats_for_pubsub.room_throttled);
+                    stats::bump(&stats_for_pubsub.messages_dropped);
+                    stats::bump(&stats_for_pubsub.auto_mutes);
+                    println!(
+                        "{label} {} {id_prefix} is flooding; muted for {}s",
+                        ui::paint(ui::YELLOW, "[flood]"),
+                        ratelimit::AUTO_MUTE_DURATION.as_secs()
+                    );
+                    continue;
+                }
+            }
+
+            presence_for_pubsub
+                .write()
+                .await
+                .stop_typing(&room, sender_id);
+
+            let (group_msg, authenticity) = match signing::decode_signed(&msg.data, sender_id) {
+                Some((gm, authenticity)) => (Some(gm), authenticity),
+                None => (None, Authenticity::Unsigned),
+            };
+            stats::bump(match authenticity {
+                Authenticity::Verified => &stats_for_pubsub.room_signed,
+                Authenticity::Unsigned => &stats_for_pubsub.room_unsigned,
+                Authenticity::Invalid(_) => &stats_for_pubsub.room_bad_signature,
+            });
+
+            if let Some(ref gm) = group_msg {
+                // Membership changes must come from the peer they name.
+                let control = gm.message_type == MessageType::GroupJoin.to_string()
+                    || gm.message_type == MessageType::GroupLeave.to_string()
+                    || gm.message_type == MessageType::Moderation.to_string();
+                if control && authenticity != Authenticity::Verified {
+                    println!(
+                        "{label} ignoring {} from {id_prefix}: not signed by the sender",
+                        gm.message_type
+                    );
+                    continue;
+                }
+                if gm.message_type == MessageType::GroupJoin.to_string() {
+                    match handle_group_join(
+                        &node_for_pubsub,
+                        &signer_for_pubsub,
+                        &private_rooms_for_pubsub,
+                        &moderation_for_pubsub,
+                        &room,
+                        sender_id,
+                        &gm.content,
+                    )
+                    .await
+                    {
+                        Ok(true) => println!("{label} {id_prefix} joined (invite verified)"),
+                        Ok(false) => {}
+                        Err(e) => println!("{label} rejected join from {id_prefix}: {e:#}"),
+                    }
+                    continue;
+                }
+            }
+
+            if !private_rooms_for_pubsub
+                .read()
+                .await
+                .admits(&room, sender_id)
+            {
+                if hidden_notified.insert((room.clone(), sender_id.clone())) {
+                    println!("{label} hiding messages from uninvited peer {id_prefix}");
+                }
+                stats::bump(&stats_for_pubsub.messages_dropped);
+                continue;
+            }
+
+            if let Some(ref gm) = group_msg {
+                if gm.message_type == MessageType::GroupLeave.to_string() {
+                    {
+                        let mut rooms = private_rooms_for_pubsub.write().await;
+                        if !rooms.remove_member(&room, sender_id) {
+                            continue;
+                        }
+                        if let Err(e) = rooms.save() {
+                            tracing::warn!("failed to save private rooms: {e:#}");
+                        }
+                    }
+                    println!("{label} {id_prefix} left");
+                    let we_created = private_rooms_for_pubsub
+                        .read()
+                        .await
+                        .get(&room)
+                        .is_some_and(|r| r.creator_id == my_identity);
+                    if we_created {
+                        spawn_key_rotation(
+                            node_for_pubsub.clone(),
+                            private_rooms_for_pubsub.clone(),
+                            room,
+                            my_identity.clone(),
+                        );
+                    }
+                    continue;
+                }
+                if gm.message_type == MessageType::Moderation.to_string() {
+                    match handle_moderation(
+                        &private_rooms_for_pubsub,
+                        &moderation_for_pubsub,
+                        &room,
+                        &gm.content,
+                    )
+                    .await
+                    {
+                        Ok(Some(action)) => {
+                            let line = describe_action(&*peers_for_pubsub.read().await, &action);
+                            println!("{label} {} {line}", ui::paint(ui::YELLOW, "[mod]"));
+                            if action.target == my_identity {
+                                println!(
+                                    "{}",
+                                    ui::paint(
+                                        ui::RED,
+                                        format!("You were {} in {room}", action.kind)
+                                    )
+                                );
+                            }
+                            honor_moderation(
+                                &node_for_pubsub,
+                                &private_rooms_for_pubsub,
+                                &action,
+                                &my_identity,
+                            )
+                            .await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            println!("{label} rejected moderation from {id_prefix}: {e:#}")
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            let owner = private_rooms_for_pubsub
+                .read()
+                .await
+                .get(&room)
+                .map(|r| r.creator_id.clone());
+            let hidden = {
+                let blocklist = blocklist_for_pubsub.read().await;
+                blocklist.is_room_muted(&room) || blocklist.hides_peer(sender_id)
+            } || match owner {
+                Some(ref owner) => moderation_for_pubsub
+                    .read()
+                    .await
+                    .roles(&room, owner)
+                    .is_muted(sender_id),
+                None => false,
+            };
+            if hidden {
+                stats::bump(&stats_for_pubsub.messages_dropped);
+                continue;
+            }
+
+            let known_name = {
+                let peers = peers_for_pubsub.read().await;
+                peers.get(sender_id).cloned()
+            };
+            let encrypted_room = private_rooms_for_pubsub
+                .read()
+                .await
+                .current_key(&room)
+                .is_some();
+            let history_key = history::room_key(&room);
+            let incoming = |gm: &GroupMessage, name: &str| {
+                history::Entry::new(&gm.id, sender_id, name, gm.timestamp, &gm.content)
+            };
+            // The history id lets mentions be looked for in the message's
+            // own text rather than in quotes of other messages.
+            let (sender_name, display_content, message_id) = match group_msg {
+                Some(gm) if gm.message_type == MessageType::Encrypted.to_string() => {
+                    let name = known_name.unwrap_or_else(|| id_prefix.to_string());
+                    let opened = {
+                        let rooms = private_rooms_for_pubsub.read().await;
+                        e2e::envelope_epoch(&gm.content).and_then(|epoch| {
+                            let key = rooms
+                                .key_for(&room, epoch)
+                                .with_context(|| format!("no key for epoch {epoch}"))?;
+                            e2e::open(&key, &room, &gm.content)
+                        })
+                    };
+                    let (line, id) = match opened {
+                        Ok(inner)
+                            if is_history_type(&inner.message_type)
+                                && !valid_message_ids(
+                                    &inner.message_type,
+                                    &inner.id,
+                                    &inner.content,
+                                ) =>
+                        {
+                            stats::bump(&stats_for_pubsub.messages_dropped);
+                            continue;
+                        }
+                        Ok(inner) if is_history_type(&inner.message_type) => {
+                            let body = apply_message(
+                                &history_for_pubsub,
+                                &documents_for_pubsub,
+                                &history_key,
+                                &inner.message_type,
+                                incoming(&inner, &name),
+                            )
+                            .await;
+                            (
+                                format!("🔒 {}@{}: {}", name, id_prefix, body),
+                                Some(inner.id),
+                            )
+                        }
+                        Ok(inner) => (
+                            format!("🔒 {}@{}: {}", name, id_prefix, inner.content),
+                            None,
+                        ),
+                        Err(e) => (
+                            format!("🔒 {}@{}: [cannot decrypt: {e:#}]", name, id_prefix),
+                            None,
+                        ),
+                    };
+                    (name, line, id)
+                }
+                Some(gm)
+                    if is_history_type(&gm.message_type)
+                        && !valid_message_ids(&gm.message_type, &gm.id, &gm.content) =>
+                {
+                    stats::bump(&stats_for_pubsub.messages_dropped);
+                    continue;
+                }
+                Some(gm) if is_history_type(&gm.message_type) => {
+                    let name = known_name.unwrap_or_else(|| id_prefix.to_string());
+                    let body = apply_message(
+                        &history_for_pubsub,
+                        &documents_for_pubsub,
+                        &history_key,
+                        &gm.message_type,
+                        incoming(&gm, &name),
+                    )
+                    .await;
+                    let line = format!("{}@{}: {}", name, id_prefix, body);
+                    if encrypted_room {
+                        (name, format!("⚠ unencrypted {line}"), Some(gm.id))
+                    } else {
+                        (name, line, Some(gm.id))
+                    }
+                }
+                _ => {
+                    let (name, line) = decode_room_message(&msg.data, id_prefix, known_name);
+                    if encrypted_room {
+                        (name, format!("⚠ unencrypted {line}"), None)
+                    } else {
+                        (name, line, None)
+                    }
+                }
+            };
+
+            learn_peer_name(
+                &peers_for_pubsub,
+                &contacts_for_pubsub,
+                sender_id,
+                &sender_name,
+            )
+            .await;
+
+            let flag = match authenticity {
+                Authenticity::Verified => String::new(),
+                Authenticity::Unsigned => format!("{} ", ui::paint(ui::YELLOW, "[unsigned]")),
+                Authenticity::Invalid(e) => format!(
+                    "{} ",
+                    ui::paint(ui::RED, format!("[bad signature: {}]", sanitize::text(&e)))
+                ),
+            };
+            let display_content = sanitize::text(&display_content);
+            let peers = peers_for_pubsub.read().await.clone();
+            let directory = mentions::Directory {
+                my_identity: &my_identity,
+                my_name: &my_name,
+                peers: &peers,
+            };
+            let mentioned = match message_id {
+                Some(id) => history_for_pubsub
+                    .write()
+                    .await
+                    .get(&history_key, &id)
+                    .is_some_and(|e| !e.deleted && directory.mentions_me(&e.content)),
+                None => directory.mentions_me(&display_content),
+            };
+            println!(
+                "{} {}{}",
+                label,
+                flag,
+                markup::render_with(&display_content, &|plain| directory.highlight(plain))
+            );
+            if mentioned {
+                notify::notify(
+                    &notify_for_pubsub,
+                    "mention",
+                    &sanitize::text(&sender_name),
+                    &display_content,
+                );
+            }
+        }
+    });
+
+    let notify_settings = settings.notify.clone();
+    let my_identity = identity.clone();
+    let private_rooms_for_dm = private_rooms.clone();
+    let peers_for_dm = peers.clone();
+    let contacts_for_dm = contacts.clone();
+    let blocklist_for_dm = blocklist.clone();
+    let blocked_response = settings.blocked_response;
+    let stats_for_dm = stats.clone();
+    let history_for_dm = history.clone();
+    let documents_for_dm = documents.clone();
+    let markers_for_dm = read_markers.clone();
+    let presence_for_dm = presence.clone();
+    let rooms_for_dm = joined_rooms.clone();
+    let public_rooms = settings.rooms.clone();
+
+    // DM handler
+    tokio::spawn(async move {
+        let mut limiter = RateLimiter::new(ratelimit::DM_RATE, ratelimit::DM_BURST);
+        while let Some((from, data, response_tx)) = dm_rx.recv().await {
+            stats::bump(&stats_for_dm.messages_received);
+            if data.len() > MAX_MESSAGE_SIZE_BYTES {
+                stats::bump(&stats_for_dm.messages_dropped);
+                continue;
+            }
+            let (blocked, muted) = {
+                let blocklist = blocklist_for_dm.read().await;
+                (blocklist.is_blocked(&from), blocklist.hides_peer(&from))
+            };
+            if blocked {
+                // Rejected unseen, contact requests included.
+                stats::bump(&stats_for_dm.messages_dropped);
+                if blocked_response == BlockedResponse::Nack {
+                    let _ = response_tx.send(AckResponse::rejected().to_bytes());
+                }
+                continue;
             }
             let from_short = &from[..8.min(from.len())];
+            match limiter.check(&from, Instant::now()) {
+                Verdict::Allow => {}
+                verdict => {
+                    stats::bump(&stats_for_dm.dm_throttled);
+                    stats::bump(&stats_for_dm.messages_dropped);
+                    if verdict == Verdict::AutoMuted {
+                        stats::bump(&stats_for_dm.auto_mutes);
+                        println!(
+                            "{} {} is flooding DMs; ignored for {}s",
+                            ui::paint(ui::YELLOW, "[flood]"),
+                            from_short,
+                            ratelimit::AUTO_MUTE_DURATION.as_secs()
+                        );
+                    }
+                    let _ = response_tx.send(AckResponse::rejected().to_bytes());
+                    continue;
+                }
+            }
             match postcard::from_bytes::<DirectMessage>(&data) {
+                Ok(dm) if dm.message_type == MessageType::GroupInvite.to_string() => {
+                    let line = match accept_group_invite(
+                        &private_rooms_for_dm,
+                        &rooms_for_dm,
+                        &public_rooms,
+                        &from,
+                        &my_identity,
+                        &dm.content,
+                    )
+                    .await
+                    {
+                        Ok((room, true)) => {
+                            format!("private room {room} (type /accept {room} to accept)")
+                        }
+                        Ok((room, false)) => format!("room {room} (type /room {room} to join)"),
+                        Err(e) => format!("rejected: {e:#}"),
+                    };
+                    println!(
+                        "{} [group invite] {}",
+                        ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
+                        sanitize::text(&line)
+                    );
+                    let _ = response_tx.send(AckResponse::success().to_bytes());
+                }
+                Ok(dm) if dm.message_type == MessageType::GroupKey.to_string() => {
+                    let line =
+                        match accept_group_key(&private_rooms_for_dm, &from, &dm.content).await {
+                            Ok((room, epoch)) => format!("room {room} now uses key epoch {epoch}"),
+                            Err(e) => format!("rejected: {e:#}"),
+                        };
+                    println!(
+                        "{} [group key] {}",
+                        ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
+                        sanitize::text(&line)
+                    );
+                    let _ = response_tx.send(AckResponse::success().to_bytes());
+                }
+                Ok(dm) if dm.message_type == MessageType::ReadReceipt.to_string() => {
+                    let key = history::dm_key(&from);
+                    let ids: Vec<&str> = dm.content.split(',').map(str::trim).collect();
+                    let mut history = history_for_dm.write().await;
+                    let mut markers = markers_for_dm.write().await;
+                    if let Some(entry) = markers.mark_peer_read(&key, history.entries(&key), &ids) {
+                        println!(
+                            "{} read \"{}\"",
+                            ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
+                            sanitize::text(&snippet(&entry.content))
+                        );
+                        if let Err(e) = markers.save() {
+                            tracing::warn!("failed to save read markers: {e:#}");
+                        }
+                    }
+                    let _ = response_tx.send(AckResponse::success().to_bytes());
+                }
+                Ok(dm) if dm.message_type == MessageType::Presence.to_string() => {
+                    let typing = PresencePayload::parse(&dm.content).is_ok_and(|p| p.typing);
+                    let mut presence = presence_for_dm.write().await;
+                    if presence.enabled
+                        && typing
+                        && !muted
+                        && presence.dm_typing(&from, Instant::now())
+                    {
+                        println!(
+                            "{} typing…",
+                            ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short))
+                        );
+                    }
+                    let _ = response_tx.send(AckResponse::success().to_bytes());
+                }
+                Ok(dm)
+                    if is_history_type(&dm.message_type)
+                        && !valid_message_ids(&dm.message_type, &dm.id, &dm.content) =>
+                {
+                    stats::bump(&stats_for_dm.messages_dropped);
+                    let _ = response_tx.send(AckResponse::rejected().to_bytes());
+                }
+                Ok(dm) if is_history_type(&dm.message_type) => {
+                    presence_for_dm.write().await.stop_dm_typing(&from);
+                    let name = peers_for_dm
+                        .read()
+                        .await
+                        .get(&from)
+                        .cloned()
+                        .unwrap_or_else(|| from_short.to_string());
+                    let entry =
+                        history::Entry::new(&dm.id, &from, &name, dm.timestamp, &dm.content);
+                    let key = history::dm_key(&from);
+                    let body = apply_message(
+                        &history_for_dm,
+                        &documents_for_dm,
+                        &key,
+                        &dm.message_type,
+                        entry,
+                    )
+                    .await;
+                    {
+                        let mut markers = markers_for_dm.write().await;
+                        if markers.track(&key) {
+                            if let Err(e) = markers.save() {
+                                tracing::warn!("failed to save read markers: {e:#}");
+                            }
+                        }
+                    }
+                    println!(
+                        "{} {}",
+                        ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
+                        markup::render(&sanitize::text(&body))
+                    );
+                    let _ = response_tx.send(AckResponse::success().to_bytes());
+                    let alerts = dm.message_type == MessageType::Text.to_string()
+                        || dm.message_type == MessageType::Reply.to_string();
+                    if alerts && !muted {
+                        notify::notify(&notify_settings, "dm", from_short, &sanitize::text(&body));
+                    }
+                }
                 Ok(dm) => {
+                    if dm.message_type == MessageType::ContactRequest.to_string()
+                        || dm.message_type == MessageType::ContactAccepted.to_string()
+                    {
+                        learn_peer_name(&peers_for_dm, &contacts_for_dm, &from, &dm.content).await;
+                    }
                     let tag = match dm.message_type.as_str() {
-                        "text" => "",
                         "contactRequest" => " [contact request]",
                         "contactAccepted" => " [contact accepted]",
-                        "readReceipt" => " [read receipt]",
-                        "groupInvite" => " [group invite]",
                         "vibe" => " [vibe]",
                         "profileUpdate" => " [profile update]",
                         other => {
                             println!(
-                                "\x1b[35m[dm ← {}]\x1b[0m [{}] {}",
-                                from_short,
+                                "{} [{}] {}",
+                                ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
                                 other,
-                                sanitize_text(&dm.content)
+                                sanitize::text(&dm.content)
                             );
                             let _ = response_tx.send(AckResponse::success().to_bytes());
                             continue;
                         }
                     };
                     println!(
-                        "\x1b[35m[dm ← {}]\x1b[0m{} {}",
-                        from_short,
+                        "{}{} {}",
+                        ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
                         tag,
-                        sanitize_text(&dm.content)
+                        sanitize::text(&dm.content)
                     );
                     let _ = response_tx.send(AckResponse::success().to_bytes());
                 }
                 Err(_) => {
                     let text = String::from_utf8_lossy(&data);
                     println!(
-                        "\x1b[35m[dm ← {}]\x1b[0m {}",
-                        from_short,
-                        sanitize_text(&text)
+                        "{} {}",
+                        ui::paint(ui::MAGENTA, format!("[dm ← {}]", from_short)),
+                        markup::render(&sanitize::text(&text))
                     );
                     let _ = response_tx.send(b"received".to_vec());
+                    if !muted {
+                        notify::notify(&notify_settings, "dm", from_short, &sanitize::text(&text));
+                    }
                 }
             }
         }
     });
 
+    spawn_roster(
+        node.clone(),
+        joined_rooms.clone(),
+        peers.clone(),
+        blocklist.clone(),
+    );
+    if settings.presence {
+        spawn_presence(
+            node.clone(),
+            signer.clone(),
+            joined_rooms.clone(),
+            private_rooms.clone(),
+            presence.clone(),
+        );
+    }
+
     print_help();
 
+    let active_key = history::room_key(&active_room);
+    if !history.write().await.entries(&active_key).is_empty() {
+        println!("\nRecent messages in {active_room}:");
+        replay_conversation(&history, &read_markers, &active_key, &identity, 10).await;
+    }
+
     // Read stdin on a blocking OS thread, bridge to async via channel.
     let (stdin_tx, mut stdin_rx) = tokio::sync::mpsc::channel::<String>(16);
     std::thread::spawn(move || {
@@ -496,28 +2625,148 @@ async fn main() -> Result<()> {
         }
     });
 
-    while let Some(line) = stdin_rx.recv().await {
-        let line = line.trim();
-        if line.is_empty() {
+    // Multiline message being composed with /paste
+    let mut draft: Option<compose::Draft> = None;
+    // Peer of the DM conversation opened with /query
+    let mut query: Option<String> = None;
+
+    loop {
+        if let (Some(peer), None) = (&query, &draft) {
+            print_query_prompt(&peer_label(&peers, peer).await);
+        }
+        let Some(input) = stdin_rx.recv().await else {
+            break;
+        };
+        // Messages produced by the input below, sent after it is handled
+        let mut pending_room_message = None;
+        let mut pending_dm: Option<(String, DirectMessage)> = None;
+
+        let line = if let Some(mut composing) = draft.take() {
+            let outcome = composing.feed(&input);
+            let peer = composing.peer.clone();
+            let budget = composing.budget;
+            let (as_document, text) = match outcome {
+                compose::Outcome::Pending => {
+                    let peer = composing.peer.as_deref();
+                    send_typing(
+                        &node,
+                        &signer,
+                        &private_rooms,
+                        &presence,
+                        &active_room,
+                        peer,
+                    )
+                    .await;
+                    draft = Some(composing);
+                    continue;
+                }
+                compose::Outcome::Cancelled => {
+                    println!("Paste discarded.");
+                    continue;
+                }
+                compose::Outcome::Overflow => {
+                    println!(
+                        "Paste discarded: larger than {} bytes.",
+                        document::MAX_DOCUMENT_BYTES
+                    );
+                    continue;
+                }
+                compose::Outcome::TooLarge(size) => {
+                    println!(
+                        "The paste has {size} bytes, more than a message holds ({budget}). \
+                         Send it as a document attachment instead? [y/N]"
+                    );
+                    draft = Some(composing);
+                    continue;
+                }
+                compose::Outcome::Message(text) => (false, text),
+                compose::Outcome::Document(text) => (true, text),
+            };
+            let payload = if as_document {
+                let file_name = format!("paste-{}.txt", current_timestamp_ms());
+                match DocumentPayload::from_text(&file_name, &text) {
+                    Ok(payload) if payload.to_json().len() > budget => {
+                        println!("Paste discarded: still too large after compression.");
+                        continue;
+                    }
+                    Ok(payload) => Some(payload),
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            match (peer, payload) {
+                (Some(peer), Some(payload)) => {
+                    pending_dm = Some((peer, DirectMessage::document(&payload)));
+                }
+                (Some(peer), None) => pending_dm = Some((peer, DirectMessage::text(&text))),
+                (None, Some(payload)) => {
+                    pending_room_message = Some(GroupMessage::document(&payload, &active_room));
+                }
+                (None, None) => {
+                    pending_room_message = Some(GroupMessage::text(&text, &active_room))
+                }
+            }
+            ""
+        } else {
+            input.trim()
+        };
+        if line.is_empty() && pending_room_message.is_none() && pending_dm.is_none() {
             continue;
         }
 
         match line {
+            // A finished /paste, sent below
+            "" => {}
             "/quit" => {
+                let key = history::room_key(&active_room);
+                mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                if settings.presence {
+                    let payload = PresencePayload {
+                        state: presence::State::Offline,
+                        status: None,
+                        typing: false,
+                    };
+                    for room in joined_rooms.read().await.iter() {
+                        publish_presence(&node, &signer, &private_rooms, room, &payload).await;
+                    }
+                }
                 println!("Goodbye!");
                 break;
             }
             "/help" => {
                 print_help();
             }
+            "/status" => {
+                let rooms = joined_rooms.read().await.clone();
+                print_status(&node, &rooms, &active_room, &private_rooms).await;
+            }
+            "/bootstrap" => {
+                println!("Bootstrapping from public Korium network...");
+                match bootstrap::bootstrap_public(&node).await {
+                    Ok(()) => println!("Bootstrap successful!"),
+                    Err(e) => eprintln!("{e:#}"),
+                }
+                let rooms = joined_rooms.read().await.clone();
+                print_status(&node, &rooms, &active_room, &private_rooms).await;
+            }
             "/peers" => {
                 let guard = peers.read().await;
                 if guard.is_empty() {
                     println!("No peers discovered yet.");
                 } else {
                     println!("Known peers:");
-                    for (id_prefix, name) in guard.iter() {
-                        println!("  {name} ({id_prefix})");
+                    let contacts = contacts.read().await;
+                    for (peer_id, name) in guard.iter() {
+                        let verified = if contacts.is_verified(peer_id) {
+                            format!(" {}", ui::paint(ui::GREEN, "✓ verified"))
+                        } else {
+                            String::new()
+                        };
+                        println!("  {} ({}){verified}", sanitize::text(name), &peer_id[..8]);
                     }
                 }
             }
@@ -530,9 +2779,15 @@ async fn main() -> Result<()> {
 
                 println!();
                 println!(
-                    "\x1b[1m── Fabric (QUIC) ── {} peers, {} connected\x1b[0m",
-                    fab_all.len(),
-                    fab_connected.len()
+                    "{}",
+                    ui::paint(
+                        ui::BOLD,
+                        format!(
+                            "── Fabric (QUIC) ── {} peers, {} connected",
+                            fab_all.len(),
+                            fab_connected.len()
+                        )
+                    )
                 );
                 if fab_all.is_empty() {
                     println!("  (none)");
@@ -540,9 +2795,9 @@ async fn main() -> Result<()> {
                     for c in &fab_all {
                         let short = &hex::encode(c.identity.as_bytes())[..16];
                         let status = if fab_connected_ids.contains(&c.identity) {
-                            "\x1b[32mconnected\x1b[0m"
+                            ui::paint(ui::GREEN, "connected")
                         } else {
-                            "\x1b[31mdisconnected\x1b[0m"
+                            ui::paint(ui::RED, "disconnected")
                         };
                         let addrs = c.addrs.join(", ");
                         println!("  {}..  [{}]  {}", short, status, addrs);
@@ -553,8 +2808,11 @@ async fn main() -> Result<()> {
                 let transport_peers = node.transport_peers();
                 println!();
                 println!(
-                    "\x1b[1m── Transport (UDP) ── {} peers\x1b[0m",
-                    transport_peers.len()
+                    "{}",
+                    ui::paint(
+                        ui::BOLD,
+                        format!("── Transport (UDP) ── {} peers", transport_peers.len())
+                    )
                 );
                 if transport_peers.is_empty() {
                     println!("  (none)");
@@ -572,7 +2830,13 @@ async fn main() -> Result<()> {
                 // ── DHT Routing ────────────────────────────────────────
                 let routing = node.get_peers().await;
                 println!();
-                println!("\x1b[1m── DHT Routing ── {} contacts\x1b[0m", routing.len());
+                println!(
+                    "{}",
+                    ui::paint(
+                        ui::BOLD,
+                        format!("── DHT Routing ── {} contacts", routing.len())
+                    )
+                );
                 if routing.is_empty() {
                     println!("  (none)");
                 } else {
@@ -591,9 +2855,15 @@ async fn main() -> Result<()> {
                     .collect();
                 println!();
                 println!(
-                    "\x1b[1m── GossipSub ── {} topics, {} unique peers\x1b[0m",
-                    topic_peers.len(),
-                    total_unique.len()
+                    "{}",
+                    ui::paint(
+                        ui::BOLD,
+                        format!(
+                            "── GossipSub ── {} topics, {} unique peers",
+                            topic_peers.len(),
+                            total_unique.len()
+                        )
+                    )
                 );
                 if topic_peers.is_empty() {
                     println!("  (none)");
@@ -607,11 +2877,11 @@ async fn main() -> Result<()> {
                         );
                         for p in &tp.eager_peers {
                             let short = &hex::encode(p.as_bytes())[..16];
-                            println!("    \x1b[32meager\x1b[0m  {}.. ", short);
+                            println!("    {}  {}.. ", ui::paint(ui::GREEN, "eager"), short);
                         }
                         for p in &tp.lazy_peers {
                             let short = &hex::encode(p.as_bytes())[..16];
-                            println!("    \x1b[33mlazy\x1b[0m   {}.. ", short);
+                            println!("    {}   {}.. ", ui::paint(ui::YELLOW, "lazy"), short);
                         }
                     }
                 }
@@ -619,7 +2889,10 @@ async fn main() -> Result<()> {
                 // ── DHT Store ──────────────────────────────────────────
                 let store = node.list_dht_store().await;
                 println!();
-                println!("\x1b[1m── DHT Store ── {} entries\x1b[0m", store.len());
+                println!(
+                    "{}",
+                    ui::paint(ui::BOLD, format!("── DHT Store ── {} entries", store.len()))
+                );
                 if store.is_empty() {
                     println!("  (none)");
                 } else {
@@ -695,121 +2968,1110 @@ async fn main() -> Result<()> {
                     t.tunnel_total_sessions
                 );
                 println!(
-                    "║ Tunnel Ingress   : {:>6} bytes                                 ║",
-                    t.tunnel_bytes_relayed_ingress
+                    "║ Tunnel Ingress   : {:>6} bytes                                 ║",
+                    t.tunnel_bytes_relayed_ingress
+                );
+                println!(
+                    "║ Tunnel Egress    : {:>6} bytes                                 ║",
+                    t.tunnel_bytes_relayed_egress
+                );
+                if !t.tier_centroids.is_empty() {
+                    let tiers: Vec<String> = t
+                        .tier_centroids
+                        .iter()
+                        .zip(t.tier_counts.iter())
+                        .map(|(c, n)| format!("{:.0}ms({})", c, n))
+                        .collect();
+                    println!("║ Latency Tiers    : {:<45} ║", tiers.join(", "));
+                }
+                println!("╠════════════════════════════════════════════════════════════════╣");
+                println!(
+                    "║ Signed Msgs      : {:>6}                                       ║",
+                    stats::get(&stats.room_signed)
+                );
+                println!(
+                    "║ Unsigned Msgs    : {:>6}                                       ║",
+                    stats::get(&stats.room_unsigned)
+                );
+                println!(
+                    "║ Bad Signatures   : {:>6}                                       ║",
+                    stats::get(&stats.room_bad_signature)
+                );
+                println!(
+                    "║ Throttled Room   : {:>6}                                       ║",
+                    stats::get(&stats.room_throttled)
+                );
+                println!(
+                    "║ Throttled DMs    : {:>6}                                       ║",
+                    stats::get(&stats.dm_throttled)
+                );
+                println!(
+                    "║ Auto-mutes       : {:>6}                                       ║",
+                    stats::get(&stats.auto_mutes)
+                );
+                println!(
+                    "║ Messages Sent    : {:>6}                                       ║",
+                    stats::get(&stats.messages_sent)
+                );
+                println!(
+                    "║ Messages Recv    : {:>6}                                       ║",
+                    stats::get(&stats.messages_received)
+                );
+                println!(
+                    "║ Messages Dropped : {:>6}                                       ║",
+                    stats::get(&stats.messages_dropped)
+                );
+                println!(
+                    "║ ACK Failures     : {:>6}                                       ║",
+                    stats::get(&stats.ack_failures)
+                );
+                println!("╚════════════════════════════════════════════════════════════════╝");
+            }
+            _ if line.starts_with("/dm ") => {
+                let parts: Vec<&str> = line.splitn(3, ' ').collect();
+                if parts.len() < 3 {
+                    println!("Usage: /dm <identity_hex> <message>");
+                    continue;
+                }
+
+                let peer_identity = parts[1];
+                let message = parts[2];
+
+                if message.len() > MAX_MESSAGE_SIZE_BYTES {
+                    println!("Message too large (max {} bytes)", MAX_MESSAGE_SIZE_BYTES);
+                    continue;
+                }
+
+                if let Err(e) = validate_identity(peer_identity) {
+                    println!("{e}");
+                    continue;
+                }
+
+                pending_dm = Some((peer_identity.to_string(), DirectMessage::text(message)));
+            }
+            _ if line == "/paste" || line.starts_with("/paste ") => {
+                let peer = match line["/paste".len()..].trim() {
+                    "" => query.clone(),
+                    query => match resolve_peer(&peers, query).await {
+                        Ok(id) => Some(id),
+                        Err(e) => {
+                            println!("{e:#}");
+                            continue;
+                        }
+                    },
+                };
+                let target = match peer {
+                    Some(ref id) => format!("DM to {}", &id[..8]),
+                    None => active_room.clone(),
+                };
+                println!(
+                    "Composing for {target}. End with a line containing only \"{}\", or {} to discard.",
+                    compose::TERMINATOR,
+                    compose::CANCEL
+                );
+                let sealed = peer.is_none()
+                    && private_rooms
+                        .read()
+                        .await
+                        .current_key(&active_room)
+                        .is_some();
+                draft = Some(compose::Draft::new(peer, sealed));
+            }
+            _ if line.starts_with("/contact ") => {
+                let parts: Vec<&str> = line.splitn(2, ' ').collect();
+                if parts.len() < 2 {
+                    println!("Usage: /contact <identity_hex>");
+                    continue;
+                }
+
+                let peer_identity = parts[1];
+
+                if let Err(e) = validate_identity(peer_identity) {
+                    println!("{e}");
+                    continue;
+                }
+
+                let req = DirectMessage::contact_request(&settings.name);
+                match send_direct(&node, peer_identity, &req).await {
+                    Ok(status) => {
+                        let status = match status {
+                            AckStatus::Acked => "sent",
+                            _ => "sent (legacy peer)",
+                        };
+                        println!(
+                            "{} {} [{}]",
+                            ui::paint(ui::CYAN, format!("[contact → {}]", &peer_identity[..8])),
+                            settings.name,
+                            status
+                        );
+                    }
+                    Err(e) => eprintln!("{} {e}", ui::paint(ui::RED, "[contact error]")),
+                }
+            }
+            _ if line.starts_with("/join-peer ") => {
+                let words: Vec<String> = line["/join-peer ".len()..]
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect();
+                let targets = match bootstrap::expand_runtime_join_targets(&words).await {
+                    Ok(t) if !t.is_empty() => t,
+                    Ok(_) => {
+                        println!("Usage: /join-peer <address>/<identity_hex>... | @<file>");
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                println!("Joining {} peer(s)...", targets.len());
+                for report in bootstrap::join_all(&node, &targets).await {
+                    println!("{report}");
+                }
+                let rooms = joined_rooms.read().await.clone();
+                print_status(&node, &rooms, &active_room, &private_rooms).await;
+            }
+            _ if line == "/invite-code" || line.starts_with("/invite-code ") => {
+                let ttl = match line["/invite-code".len()..].trim() {
+                    "" => None,
+                    ttl => match invite::parse_ttl(ttl) {
+                        Ok(d) => Some(d),
+                        Err(e) => {
+                            println!("{e}");
+                            continue;
+                        }
+                    },
+                };
+                let code =
+                    match invite::Invite::new(&display_addr, &identity, Some(&active_room), ttl)
+                        .and_then(|invite| invite.encode())
+                    {
+                        Ok(c) => c,
+                        Err(e) => {
+                            eprintln!("{e:#}");
+                            continue;
+                        }
+                    };
+                match invite::render_qr(&code) {
+                    Ok(qr) => println!("{qr}"),
+                    Err(e) => eprintln!("{e:#}"),
+                }
+                println!("Invite code for room {active_room}:");
+                println!("{code}");
+                if let Some(ttl) = ttl {
+                    println!("Expires in {}s", ttl.as_secs());
+                }
+            }
+            _ if line == "/accept" || line.starts_with("/accept ") => {
+                let room = line["/accept".len()..].trim();
+                if room.is_empty() {
+                    let rooms = private_rooms.read().await;
+                    let mut pending = rooms.pending_invites().peekable();
+                    if pending.peek().is_none() {
+                        println!("No pending invites.");
+                    }
+                    for p in pending {
+                        println!(
+                            "  {} from {}",
+                            p.invite.group_id,
+                            peer_label(&peers, &p.invite.creator_id).await
+                        );
+                    }
+                    continue;
+                }
+                if let Err(e) =
+                    check_invitable(&private_rooms, &joined_rooms, &settings.rooms, room).await
+                {
+                    println!("{e:#}");
+                    continue;
+                }
+                let mut rooms = private_rooms.write().await;
+                match rooms.accept_invite(room).and_then(|()| rooms.save()) {
+                    Ok(()) => println!("Accepted the invite to {room}; /room {room} to enter it."),
+                    Err(e) => println!("{e:#}"),
+                }
+            }
+            _ if line.starts_with("/invite ") => {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 2 || parts.len() > 3 {
+                    println!("Usage: /invite <identity_hex> [ttl]");
+                    continue;
+                }
+                let peer_identity = parts[1];
+                if let Err(e) = validate_identity(peer_identity) {
+                    println!("{e}");
+                    continue;
+                }
+                let ttl = match parts.get(2) {
+                    Some(t) => match invite::parse_ttl(t) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            println!("{e}");
+                            continue;
+                        }
+                    },
+                    None => membership::DEFAULT_INVITE_TTL,
+                };
+
+                {
+                    let mut rooms = private_rooms.write().await;
+                    if !rooms.create(&active_room, &identity) {
+                        println!("Only the creator of {active_room} can invite to it.");
+                        continue;
+                    }
+                    if let Err(e) = rooms.save() {
+                        eprintln!("{e:#}");
+                    }
+                }
+
+                let invite = match RoomInvite::issue(&signer, &active_room, peer_identity, ttl) {
+                    Ok(invite) => invite,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let payload = GroupInvitePayload {
+                    group_id: active_room.clone(),
+                    name: active_room.clone(),
+                    description: String::new(),
+                    member_ids: private_rooms.read().await.members(&active_room),
+                    member_names: HashMap::from([(identity.clone(), settings.name.clone())]),
+                    creator_id: identity.clone(),
+                    created_at_ms: current_timestamp_ms(),
+                    invitee_id: Some(invite.invitee_id.clone()),
+                    expires_at_ms: Some(invite.expires_at_ms),
+                    signature: Some(invite.signature.clone()),
+                    group_key: None,
+                    key_epoch: None,
+                };
+                let payload = match private_rooms.read().await.current_key(&active_room) {
+                    Some(key) => GroupInvitePayload {
+                        group_key: Some(key.key),
+                        key_epoch: Some(key.epoch),
+                        ..payload
+                    },
+                    None => payload,
+                };
+                match send_direct(&node, peer_identity, &DirectMessage::group_invite(&payload))
+                    .await
+                {
+                    Ok(status) => {
+                        let ack = if status.is_acked() { "✓" } else { "?" };
+                        println!(
+                            "{} {} (private, expires in {}s) [{}]",
+                            ui::paint(ui::CYAN, format!("[invite → {}]", &peer_identity[..8])),
+                            active_room,
+                            ttl.as_secs(),
+                            ack
+                        );
+                    }
+                    Err(e) => eprintln!("{} {e}", ui::paint(ui::RED, "[invite error]")),
+                }
+            }
+            _ if line == "/away" || line.starts_with("/away ") || line == "/back" => {
+                if !settings.presence {
+                    println!("Presence is off; start with --presence or set presence = true.");
+                    continue;
+                }
+                let (state, status) = match line.strip_prefix("/away") {
+                    Some(status) => (presence::State::Away, Some(status.trim())),
+                    None => (presence::State::Online, None),
+                };
+                let payload = {
+                    let mut presence = presence.write().await;
+                    if !presence.set(state, status) {
+                        println!("You are already {state}.");
+                        continue;
+                    }
+                    presence.payload()
+                };
+                for room in joined_rooms.read().await.iter() {
+                    publish_presence(&node, &signer, &private_rooms, room, &payload).await;
+                }
+                match payload.status {
+                    Some(ref status) => println!("You are {state}: {status}"),
+                    None => println!("You are {state}."),
+                }
+            }
+            "/who" => {
+                let present = room_peers(&node, &active_room).await;
+                if present.is_empty() {
+                    println!("No peers in {active_room}'s mesh right now.");
+                    continue;
+                }
+                println!("In {active_room} ({} connected to you):", present.len());
+                for peer in &present {
+                    let state = presence
+                        .read()
+                        .await
+                        .describe(&active_room, peer, Instant::now());
+                    match state {
+                        Some(state) => println!("  {}  {state}", peer_label(&peers, peer).await),
+                        None => println!("  {}", peer_label(&peers, peer).await),
+                    }
+                }
+            }
+            "/members" => {
+                let rooms = private_rooms.read().await;
+                if !rooms.is_private(&active_room) {
+                    println!("{active_room} is a public room; anyone may post.");
+                    continue;
+                }
+                let names = peers.read().await;
+                println!("Verified members of {active_room}:");
+                for member in rooms.members(&active_room) {
+                    let short = &member[..8.min(member.len())];
+                    let name = names.get(&member).map_or("?", String::as_str);
+                    let role = match rooms.get(&active_room) {
+                        Some(r) if r.creator_id == member => " (creator)",
+                        _ => "",
+                    };
+                    println!("  {name} ({short}){role}");
+                }
+            }
+            _ if line.starts_with("/verify ") => {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let confirm = match parts[..] {
+                    [_, _] => false,
+                    [_, _, "confirm"] => true,
+                    _ => {
+                        println!("Usage: /verify <peer> [confirm]");
+                        continue;
+                    }
+                };
+                let peer_identity = match resolve_peer(&peers, parts[1]).await {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+                let short = &peer_identity[..8];
+                let name = peers
+                    .read()
+                    .await
+                    .get(&peer_identity)
+                    .cloned()
+                    .unwrap_or_else(|| short.to_string());
+                if confirm {
+                    let mut contacts = contacts.write().await;
+                    contacts.verify(&peer_identity, &name);
+                    match contacts.save() {
+                        Ok(()) => println!(
+                            "{} {} ({short}) marked as verified",
+                            ui::paint(ui::GREEN, "✓"),
+                            sanitize::text(&name)
+                        ),
+                        Err(e) => eprintln!("{e:#}"),
+                    }
+                    continue;
+                }
+                println!("Safety number with {} ({short}):", sanitize::text(&name));
+                println!();
+                println!(
+                    "  {}",
+                    ui::paint(ui::BOLD, contacts::safety_number(&identity, &peer_identity))
                 );
+                println!("  {}", contacts::safety_emoji(&identity, &peer_identity));
+                println!();
                 println!(
-                    "║ Tunnel Egress    : {:>6} bytes                                 ║",
-                    t.tunnel_bytes_relayed_egress
+                    "Compare this with what {} sees over a channel you trust",
+                    sanitize::text(&name)
                 );
-                if !t.tier_centroids.is_empty() {
-                    let tiers: Vec<String> = t
-                        .tier_centroids
-                        .iter()
-                        .zip(t.tier_counts.iter())
-                        .map(|(c, n)| format!("{:.0}ms({})", c, n))
-                        .collect();
-                    println!("║ Latency Tiers    : {:<45} ║", tiers.join(", "));
+                println!("(in person or on a call). If it matches, run:");
+                println!("  /verify {short} confirm");
+                if contacts.read().await.is_verified(&peer_identity) {
+                    println!("{}", ui::paint(ui::GREEN, "Already verified."));
                 }
-                println!("╚════════════════════════════════════════════════════════════════╝");
             }
-            _ if line.starts_with("/dm ") => {
-                let parts: Vec<&str> = line.splitn(3, ' ').collect();
-                if parts.len() < 3 {
-                    println!("Usage: /dm <identity_hex> <message>");
+            _ if line.starts_with("/unverify ") => {
+                let query = line["/unverify ".len()..].trim();
+                let peer_identity = match resolve_peer(&peers, query).await {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+                let mut contacts = contacts.write().await;
+                if !contacts.unverify(&peer_identity) {
+                    println!("{} is not verified.", &peer_identity[..8]);
                     continue;
                 }
-
-                let peer_identity = parts[1];
-                let message = parts[2];
-
-                if message.len() > MAX_MESSAGE_SIZE_BYTES {
-                    println!("Message too large (max {} bytes)", MAX_MESSAGE_SIZE_BYTES);
-                    continue;
+                match contacts.save() {
+                    Ok(()) => println!("{} is no longer verified", &peer_identity[..8]),
+                    Err(e) => eprintln!("{e:#}"),
                 }
-
-                if peer_identity.len() != MAX_IDENTITY_LENGTH || hex::decode(peer_identity).is_err()
-                {
-                    println!(
-                        "Invalid identity. Must be {} hex characters.",
-                        MAX_IDENTITY_LENGTH
-                    );
+            }
+            _ if line.starts_with("/block ") || line.starts_with("/unblock ") => {
+                let (command, query) = line.split_once(' ').unwrap_or((line, ""));
+                let peer_identity = match resolve_peer(&peers, query.trim()).await {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+                let short = &peer_identity[..8];
+                let mut blocklist = blocklist.write().await;
+                let changed = if command == "/block" {
+                    blocklist.block(&peer_identity)
+                } else {
+                    blocklist.unblock(&peer_identity)
+                };
+                if !changed {
+                    println!("Nothing to do for {short}.");
                     continue;
                 }
-
-                let dm = DirectMessage::text(message);
-                let payload = postcard::to_allocvec(&dm).expect("Failed to serialize message");
-
-                match tokio::time::timeout(
-                    Duration::from_secs(10),
-                    node.send(peer_identity, payload),
+                match blocklist.save() {
+                    Ok(()) if command == "/block" => println!(
+                        "{} {short}: DMs rejected, room messages hidden",
+                        ui::paint(ui::RED, "Blocked")
+                    ),
+                    Ok(()) => println!("Unblocked {short}"),
+                    Err(e) => eprintln!("{e:#}"),
+                }
+            }
+            _ if [
+                "/kick ",
+                "/ban ",
+                "/unban ",
+                "/promote ",
+                "/demote ",
+                "/mute member ",
+                "/unmute member ",
+            ]
+            .iter()
+            .any(|c| line.starts_with(c)) =>
+            {
+                let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+                let rest = match command {
+                    "/mute" | "/unmute" => rest["member ".len()..].trim_start(),
+                    _ => rest,
+                };
+                let (query, arg) = rest.split_once(' ').unwrap_or((rest, ""));
+                let kind = match command {
+                    "/kick" => ModKind::Kick,
+                    "/ban" => ModKind::Ban,
+                    "/unban" => ModKind::Unban,
+                    "/promote" => ModKind::Promote,
+                    "/demote" => ModKind::Demote,
+                    "/mute" => ModKind::Mute,
+                    _ => ModKind::Unmute,
+                };
+                let target = match resolve_peer(&peers, query).await {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+                // `/mute member` takes a duration; the others a reason.
+                let (until_ms, reason) = match kind {
+                    ModKind::Mute => {
+                        let ttl = match arg.trim() {
+                            "" => Ok(moderation::DEFAULT_MUTE),
+                            ttl => invite::parse_ttl(ttl),
+                        };
+                        match ttl.and_then(invite::expires_at) {
+                            Ok(until) => (Some(until), ""),
+                            Err(e) => {
+                                println!("{e}");
+                                continue;
+                            }
+                        }
+                    }
+                    _ => (None, arg.trim()),
+                };
+                match moderate(
+                    &node,
+                    &signer,
+                    &private_rooms,
+                    &moderation,
+                    &active_room,
+                    kind,
+                    &target,
+                    until_ms,
+                    reason,
                 )
                 .await
                 {
-                    Ok(Ok(response)) => {
-                        let ack = match postcard::from_bytes::<AckResponse>(&response) {
-                            Ok(a) if a.ack => "✓",
-                            _ => {
-                                if String::from_utf8_lossy(&response) == "received" {
-                                    "✓"
-                                } else {
-                                    "?"
-                                }
+                    Ok(action) => {
+                        let line = describe_action(&*peers.read().await, &action);
+                        println!(
+                            "{} {} {line}",
+                            ui::paint(ui::GREEN, format!("[{active_room}]")),
+                            ui::paint(ui::YELLOW, "[mod]")
+                        );
+                        honor_moderation(&node, &private_rooms, &action, &identity).await;
+                    }
+                    Err(e) => println!("{e:#}"),
+                }
+            }
+            "/mods" => {
+                let owner = match room_owner(&private_rooms, &active_room).await {
+                    Ok(owner) => owner,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let log = moderation.read().await;
+                let roles = log.roles(&active_room, &owner);
+                let names = peers.read().await;
+                let name = |id: &String| match names.get(id) {
+                    Some(n) => format!("{} ({})", sanitize::text(n), &id[..8.min(id.len())]),
+                    None => id[..8.min(id.len())].to_string(),
+                };
+                let list = |ids: Vec<&String>| {
+                    if ids.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        ids.into_iter().map(name).collect::<Vec<_>>().join(", ")
+                    }
+                };
+                println!("Moderation of {active_room}:");
+                println!("  Owner      : {}", name(&owner));
+                println!("  Moderators : {}", list(roles.moderators.iter().collect()));
+                println!("  Banned     : {}", list(roles.banned.iter().collect()));
+                println!(
+                    "  Muted      : {}",
+                    list(roles.muted.keys().filter(|m| roles.is_muted(m)).collect())
+                );
+                let entries = log.log(&active_room);
+                if !entries.is_empty() {
+                    println!("  Log (latest {}):", entries.len().min(10));
+                    let now = current_timestamp_ms();
+                    for action in entries.iter().rev().take(10).rev() {
+                        let ago = (now - action.issued_at_ms).max(0) / 60_000;
+                        println!("    {:>5}m ago  {}", ago, describe_action(&names, action));
+                    }
+                }
+            }
+            _ if line.starts_with("/mute ") || line.starts_with("/unmute ") => {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                let mute = parts[0] == "/mute";
+                let mut blocklist = blocklist.write().await;
+                let (changed, what) = match parts[1..] {
+                    ["room"] => (
+                        if mute {
+                            blocklist.mute_room(&active_room)
+                        } else {
+                            blocklist.unmute_room(&active_room)
+                        },
+                        format!("room {active_room}"),
+                    ),
+                    ["room", room] => (
+                        if mute {
+                            blocklist.mute_room(room)
+                        } else {
+                            blocklist.unmute_room(room)
+                        },
+                        format!("room {room}"),
+                    ),
+                    ["peer", query] => {
+                        let peer_identity = match resolve_peer(&peers, query).await {
+                            Ok(id) => id,
+                            Err(e) => {
+                                println!("{e}");
+                                continue;
                             }
                         };
+                        let changed = if mute {
+                            blocklist.mute_peer(&peer_identity)
+                        } else {
+                            blocklist.unmute_peer(&peer_identity)
+                        };
+                        (changed, peer_identity[..8].to_string())
+                    }
+                    _ => {
+                        println!("Usage: {} room [name] | {} peer <peer>", parts[0], parts[0]);
+                        continue;
+                    }
+                };
+                if !changed {
+                    println!("Nothing to do for {what}.");
+                    continue;
+                }
+                match blocklist.save() {
+                    Ok(()) if mute => println!("Muted {what}"),
+                    Ok(()) => println!("Unmuted {what}"),
+                    Err(e) => eprintln!("{e:#}"),
+                }
+            }
+            "/blocked" => {
+                let blocklist = blocklist.read().await;
+                let names = peers.read().await;
+                let describe = |id: &String| match names.get(id) {
+                    Some(name) => {
+                        format!("{} ({})", sanitize::text(name), &id[..8.min(id.len())])
+                    }
+                    None => id[..8.min(id.len())].to_string(),
+                };
+                let blocked: Vec<String> = blocklist.blocked().map(describe).collect();
+                let muted: Vec<String> = blocklist.muted_peers().map(describe).collect();
+                let rooms: Vec<String> = blocklist.muted_rooms().cloned().collect();
+                let or_none = |v: Vec<String>| {
+                    if v.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        v.join(", ")
+                    }
+                };
+                println!("Blocked peers : {}", or_none(blocked));
+                println!("Muted peers   : {}", or_none(muted));
+                println!("Muted rooms   : {}", or_none(rooms));
+            }
+            _ if line.starts_with("/room ") => {
+                let room = line["/room ".len()..].trim();
+                if let Err(e) = validate_room(room) {
+                    println!("{e:#}");
+                    continue;
+                }
+                let already_joined = joined_rooms.read().await.iter().any(|r| r == room);
+                if !already_joined {
+                    if let Err(e) = node.subscribe(&room_topic(room)).await {
+                        eprintln!("Failed to join room {room}: {e}");
+                        continue;
+                    }
+                    joined_rooms.write().await.push(room.to_string());
+                    println!("Subscribed to room: {room}");
+                    announce_membership(&node, &signer, &private_rooms, room).await;
+                    if settings.presence {
+                        match node.subscribe(&presence::topic(room)).await {
+                            Ok(()) => {
+                                let payload = presence.read().await.payload();
+                                publish_presence(&node, &signer, &private_rooms, room, &payload)
+                                    .await;
+                            }
+                            Err(e) => eprintln!("No presence in {room}: {e}"),
+                        }
+                    }
+                }
+                let key = history::room_key(&active_room);
+                mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                active_room = room.to_string();
+                println!(
+                    "Active room: {active_room} ({})",
+                    encryption_note(&*private_rooms.read().await, &active_room)
+                );
+                let key = history::room_key(&active_room);
+                replay_conversation(&history, &read_markers, &key, &identity, 0).await;
+            }
+            "/rekey" => {
+                match rotate_group_key(&node, &private_rooms, &active_room, &identity).await {
+                    Ok((epoch, reached, total)) => println!(
+                        "{} key rotated to epoch {} ({}/{} members reached)",
+                        active_room, epoch, reached, total
+                    ),
+                    Err(e) => println!("{e:#}"),
+                }
+            }
+            _ if line == "/leave" || line.starts_with("/leave ") => {
+                let room = match line["/leave".len()..].trim() {
+                    "" => active_room.clone(),
+                    room => room.to_string(),
+                };
+                let remaining: Vec<String> = {
+                    let rooms = joined_rooms.read().await;
+                    if !rooms.contains(&room) {
+                        println!("Not in room {room}.");
+                        continue;
+                    }
+                    rooms.iter().filter(|r| **r != room).cloned().collect()
+                };
+                let Some(next_room) = remaining.first().cloned() else {
+                    println!("Cannot leave the only joined room; /room <name> first.");
+                    continue;
+                };
+
+                let was_private = private_rooms.read().await.is_private(&room);
+                if was_private {
+                    let payload =
+                        signing::encode_signed(&signer, &GroupMessage::group_leave(&room));
+                    if let Err(e) = node.publish(&room_topic(&room), payload).await {
+                        eprintln!("Failed to announce leave: {e}");
+                    }
+                    let mut rooms = private_rooms.write().await;
+                    rooms.forget(&room);
+                    if let Err(e) = rooms.save() {
+                        eprintln!("{e:#}");
+                    }
+                    let mut log = moderation.write().await;
+                    log.forget(&room);
+                    if let Err(e) = log.save() {
+                        eprintln!("{e:#}");
+                    }
+                }
+                if let Err(e) = node.unsubscribe(&room_topic(&room)).await {
+                    eprintln!("Failed to unsubscribe from {room}: {e}");
+                }
+                if settings.presence {
+                    if let Err(e) = node.unsubscribe(&presence::topic(&room)).await {
+                        tracing::debug!("presence topic of {room} not left: {e}");
+                    }
+                }
+                *joined_rooms.write().await = remaining;
+                println!("Left room {room}");
+                if active_room == room {
+                    active_room = next_room;
+                    println!("Active room: {active_room}");
+                }
+            }
+            _ if line == "/history" || line.starts_with("/history ") => {
+                let count = match line["/history".len()..].trim() {
+                    "" => 20,
+                    n => match n.parse::<usize>() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            println!("Usage: /history [count]");
+                            continue;
+                        }
+                    },
+                };
+                let (key, title) = match query {
+                    Some(ref peer) => (history::dm_key(peer), format!("DMs with {}", &peer[..8])),
+                    None => (history::room_key(&active_room), active_room.clone()),
+                };
+                {
+                    let mut history = history.write().await;
+                    let reply_counts = history.reply_counts(&key);
+                    let entries = history.entries(&key);
+                    if entries.is_empty() {
+                        println!("No history for {title} yet.");
+                        continue;
+                    }
+                    let markers = read_markers.read().await;
+                    let unread = markers.unread(&key, entries, &identity).len();
+                    let first_unread = markers.first_unread(&key, entries);
+                    println!("History of {title}:");
+                    let start = entries.len().saturating_sub(count);
+                    for (i, entry) in entries.iter().enumerate().skip(start) {
+                        if unread > 0 && i == first_unread.max(start) {
+                            println!("{}", unread_marker(unread));
+                        }
+                        let replies = reply_counts.get(&entry.id).copied().unwrap_or(0);
                         println!(
-                            "\x1b[33m[dm → {}]\x1b[0m {} [{}]",
-                            &peer_identity[..8],
-                            message,
-                            ack
+                            "  {:>4} {}",
+                            format!("^{}", entries.len() - i),
+                            render_entry(entry, replies, None)
                         );
                     }
-                    Ok(Err(e)) => eprintln!("\x1b[31m[dm error]\x1b[0m Failed to send: {e}"),
-                    Err(_) => eprintln!("\x1b[31m[dm error]\x1b[0m Timeout: peer unreachable"),
+                }
+                let read = mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                if let Some(ref peer) = query {
+                    send_read_receipt(&node, peer, read);
                 }
             }
-            _ if line.starts_with("/contact ") => {
-                let parts: Vec<&str> = line.splitn(2, ' ').collect();
-                if parts.len() < 2 {
-                    println!("Usage: /contact <identity_hex>");
+            _ if line.starts_with("/reply ") || line.starts_with("/edit ") => {
+                let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+                let Some((reference, text)) = rest.trim_start().split_once(' ') else {
+                    println!("Usage: {command} <message id|last> <text>");
+                    continue;
+                };
+                let key = match query {
+                    Some(ref peer) => history::dm_key(peer),
+                    None => history::room_key(&active_room),
+                };
+                let target = match resolve_message_ref(&history, &key, &identity, reference).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                if command == "/edit" && target.from != identity {
+                    println!("You can only edit your own messages.");
                     continue;
                 }
-
-                let peer_identity = parts[1];
-
-                if peer_identity.len() != MAX_IDENTITY_LENGTH || hex::decode(peer_identity).is_err()
+                if let Some(ref peer) = query {
+                    let dm = if command == "/edit" {
+                        DirectMessage::edit(&target.id, text)
+                    } else {
+                        DirectMessage::reply(&target.id, text)
+                    };
+                    pending_dm = Some((peer.clone(), dm));
+                } else if command == "/edit" {
+                    pending_room_message = Some(GroupMessage::edit(&target.id, text, &active_room));
+                } else {
+                    let root = target.thread.as_deref().unwrap_or(&target.id);
+                    pending_room_message =
+                        Some(GroupMessage::reply(&target.id, root, text, &active_room));
+                }
+            }
+            _ if line.starts_with("/delete ") => {
+                let reference = line["/delete ".len()..].trim();
+                let key = match query {
+                    Some(ref peer) => history::dm_key(peer),
+                    None => history::room_key(&active_room),
+                };
+                let target = match resolve_message_ref(&history, &key, &identity, reference).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                if target.from != identity {
+                    println!("You can only delete your own messages.");
+                    continue;
+                }
+                match query {
+                    Some(ref peer) => {
+                        pending_dm = Some((peer.clone(), DirectMessage::delete(&target.id)));
+                    }
+                    None => {
+                        pending_room_message = Some(GroupMessage::delete(&target.id, &active_room));
+                    }
+                }
+            }
+            _ if line.starts_with("/save ") => {
+                let reference = line["/save ".len()..].trim();
+                let key = match query {
+                    Some(ref peer) => history::dm_key(peer),
+                    None => history::room_key(&active_room),
+                };
+                let target = match resolve_message_ref(&history, &key, &identity, reference).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let held = documents.read().await;
+                let Some(payload) = held.get(&target.id) else {
+                    println!(
+                        "{} is not a document held for saving (only the last {} received are).",
+                        target.short_id(),
+                        document::MAX_HELD
+                    );
+                    continue;
+                };
+                match document::save(&target.id, payload) {
+                    Ok(path) => println!("Saved {} to {}", payload.summary(), path.display()),
+                    Err(e) => println!("{e:#}"),
+                }
+            }
+            "/unread" => {
+                let key = history::room_key(&active_room);
+                mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                let rooms = joined_rooms.read().await.clone();
+                let dm_keys: Vec<String> = read_markers
+                    .read()
+                    .await
+                    .keys()
+                    .filter(|k| k.starts_with("dm/"))
+                    .cloned()
+                    .collect();
+                let peers = peers.read().await.clone();
+                let directory = mentions::Directory {
+                    my_identity: &identity,
+                    my_name: &settings.name,
+                    peers: &peers,
+                };
+                let mut lines = Vec::new();
+                let mut history = history.write().await;
+                let markers = read_markers.read().await;
+                for room in &rooms {
+                    let key = history::room_key(room);
+                    let unread = markers.unread(&key, history.entries(&key), &identity);
+                    if unread.is_empty() {
+                        continue;
+                    }
+                    let mentions = unread
+                        .iter()
+                        .filter(|e| directory.mentions_me(&e.content))
+                        .count();
+                    let mut line = format!(
+                        "  {} {}",
+                        ui::paint(ui::GREEN, format!("[{room}]")),
+                        unread.len()
+                    );
+                    if mentions > 0 {
+                        line.push_str(&format!(" ({mentions} mentioning you)"));
+                    }
+                    lines.push(line);
+                }
+                for key in &dm_keys {
+                    let peer = &key["dm/".len()..];
+                    let unread = markers.unread(key, history.entries(key), &identity).len();
+                    if unread == 0 {
+                        continue;
+                    }
+                    let name = peers
+                        .get(peer)
+                        .map_or(&peer[..8.min(peer.len())], String::as_str);
+                    lines.push(format!(
+                        "  {} {unread}",
+                        ui::paint(ui::MAGENTA, format!("[dm {}]", sanitize::text(name)))
+                    ));
+                }
+                if lines.is_empty() {
+                    println!("Nothing unread.");
+                } else {
+                    println!("Unread (/room <name> or /read <peer> to catch up):");
+                    for line in lines {
+                        println!("{line}");
+                    }
+                }
+            }
+            _ if line.starts_with("/read ") => {
+                let peer_identity = match resolve_peer(&peers, line["/read ".len()..].trim()).await
                 {
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let key = history::dm_key(&peer_identity);
+                if history.write().await.entries(&key).is_empty() {
+                    println!("No DMs with {} yet.", &peer_identity[..8]);
+                    continue;
+                }
+                println!("DMs with {}:", &peer_identity[..8]);
+                let read = replay_conversation(&history, &read_markers, &key, &identity, 5).await;
+                send_read_receipt(&node, &peer_identity, read);
+                let mut history = history.write().await;
+                let entries = history.entries(&key);
+                if let Some(entry) = read_markers.read().await.peer_read(&key, entries) {
+                    println!(
+                        "  They have read up to \"{}\".",
+                        sanitize::text(&snippet(&entry.content))
+                    );
+                }
+            }
+            "/mentions" => {
+                let rooms = joined_rooms.read().await.clone();
+                let peers = peers.read().await.clone();
+                let directory = mentions::Directory {
+                    my_identity: &identity,
+                    my_name: &settings.name,
+                    peers: &peers,
+                };
+                let mut found = Vec::new();
+                let mut history = history.write().await;
+                for room in &rooms {
+                    for entry in history.entries(&history::room_key(room)) {
+                        if entry.from != identity
+                            && !entry.deleted
+                            && directory.mentions_me(&entry.content)
+                        {
+                            found.push((room.clone(), entry.clone()));
+                        }
+                    }
+                }
+                if found.is_empty() {
+                    println!("No mentions in your rooms' history.");
+                    continue;
+                }
+                found.sort_by_key(|(_, entry)| entry.timestamp);
+                println!("Mentions:");
+                for (room, entry) in &found[found.len().saturating_sub(MAX_MENTIONS_SHOWN)..] {
                     println!(
-                        "Invalid identity. Must be {} hex characters.",
-                        MAX_IDENTITY_LENGTH
+                        "  {} {}",
+                        ui::paint(ui::GREEN, format!("[{room}]")),
+                        render_entry(entry, 0, Some(&directory))
                     );
+                }
+            }
+            _ if line == "/thread" || line.starts_with("/thread ") => {
+                let reference = line["/thread".len()..].trim();
+                if reference.is_empty() || reference == "close" {
+                    match active_thread.take() {
+                        Some(_) => println!("Back to the main view of {active_room}."),
+                        None => println!("Usage: /thread <message id|^N|last> | /thread close"),
+                    }
                     continue;
                 }
-
-                let req = DirectMessage::contact_request(&args.name);
-                let payload =
-                    postcard::to_allocvec(&req).expect("Failed to serialize contact request");
-
-                match tokio::time::timeout(
-                    Duration::from_secs(10),
-                    node.send(peer_identity, payload),
-                )
-                .await
+                let key = history::room_key(&active_room);
+                let target = match resolve_message_ref(&history, &key, &identity, reference).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let root = target.thread.unwrap_or(target.id);
+                let messages = history.write().await.thread(&key, &root);
+                println!("Thread {} in {active_room}:", history::short(&root));
+                if messages.first().is_none_or(|first| first.id != root) {
+                    println!("  (the first message is older than this history)");
+                }
+                for entry in &messages {
+                    println!("  {}", render_entry(entry, 0, None));
+                }
+                println!("Messages you type now go to this thread; /thread close to leave it.");
+                active_thread = Some((active_room.clone(), root));
+            }
+            _ if line.starts_with("/query ") => {
+                let peer_identity = match resolve_peer(&peers, line["/query ".len()..].trim()).await
                 {
-                    Ok(Ok(response)) => {
-                        let status = match postcard::from_bytes::<AckResponse>(&response) {
-                            Ok(a) if a.ack => "sent",
-                            _ => "sent (legacy peer)",
-                        };
-                        println!(
-                            "\x1b[36m[contact → {}]\x1b[0m {} [{}]",
-                            &peer_identity[..8],
-                            args.name,
-                            status
-                        );
+                    Ok(id) => id,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                let name = peer_label(&peers, &peer_identity).await;
+                println!(
+                    "Talking to {name}. Plain lines go to them; /close returns to {active_room}."
+                );
+                let key = history::dm_key(&peer_identity);
+                let read = replay_conversation(&history, &read_markers, &key, &identity, 10).await;
+                send_read_receipt(&node, &peer_identity, read);
+                query = Some(peer_identity);
+            }
+            "/close" => match query.take() {
+                Some(peer) => println!(
+                    "Closed the conversation with {}. Back to {active_room}.",
+                    peer_label(&peers, &peer).await
+                ),
+                None => println!("No conversation is open (/query <peer> opens one)."),
+            },
+            _ if line.starts_with("/react ") => {
+                // `/react <msg> <emoji>` in the active room, or
+                // `/react <peer> <msg> <emoji>` in a DM conversation
+                let words: Vec<&str> = line.split_whitespace().skip(1).collect();
+                let (peer, reference, emoji) = match words[..] {
+                    [reference, emoji] => (None, reference, emoji),
+                    [peer, reference, emoji] => (Some(peer), reference, emoji),
+                    _ => {
+                        println!("Usage: /react [peer] <message id|^N|last> <emoji>");
+                        continue;
+                    }
+                };
+                if !valid_reaction(emoji) {
+                    println!("A reaction is a single emoji or short word.");
+                    continue;
+                }
+                let (key, peer_identity) = match (peer, &query) {
+                    (Some(peer), _) => match resolve_peer(&peers, peer).await {
+                        Ok(id) => (history::dm_key(&id), Some(id)),
+                        Err(e) => {
+                            println!("{e:#}");
+                            continue;
+                        }
+                    },
+                    (None, Some(peer)) => (history::dm_key(peer), Some(peer.clone())),
+                    (None, None) => (history::room_key(&active_room), None),
+                };
+                let target = match resolve_message_ref(&history, &key, &identity, reference).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        println!("{e:#}");
+                        continue;
+                    }
+                };
+                match peer_identity {
+                    Some(peer) => {
+                        pending_dm = Some((peer, DirectMessage::reaction(&target.id, emoji)));
+                    }
+                    None => {
+                        pending_room_message =
+                            Some(GroupMessage::reaction(&target.id, emoji, &active_room));
                     }
-                    Ok(Err(e)) => eprintln!("\x1b[31m[contact error]\x1b[0m Failed to send: {e}"),
-                    Err(_) => eprintln!("\x1b[31m[contact error]\x1b[0m Timeout: peer unreachable"),
                 }
             }
             _ if line.starts_with('/') => {
@@ -820,20 +4082,104 @@ async fn main() -> Result<()> {
                     println!("Message too large (max {} bytes)", MAX_MESSAGE_SIZE_BYTES);
                     continue;
                 }
-                // Broadcast to room
-                let group_msg = GroupMessage::text(line, &args.room);
-                let payload =
-                    postcard::to_allocvec(&group_msg).expect("Failed to serialize message");
-                let formatted = format!("{}@{}: {}", args.name, &identity[..8], line);
-
-                if let Err(e) = node.publish(&room_topic, payload).await {
-                    eprintln!("Failed to send message: {e}");
+                // Send to the open DM conversation, or broadcast to the room
+                // (or its open thread), sealed with the group key if it has one
+                if let Some(ref peer) = query {
+                    pending_dm = Some((peer.clone(), DirectMessage::text(line)));
                 } else {
-                    println!("\x1b[32m[room]\x1b[0m {}", sanitize_text(&formatted));
+                    pending_room_message = Some(match active_thread {
+                        Some((ref room, ref root)) if *room == active_room => {
+                            GroupMessage::reply(root, root, line, &active_room)
+                        }
+                        _ => GroupMessage::text(line, &active_room),
+                    });
+                }
+            }
+        }
+
+        if let Some((peer_identity, dm)) = pending_dm {
+            match send_direct(&node, &peer_identity, &dm).await {
+                Ok(status) => {
+                    stats::bump(&stats.messages_sent);
+                    if !status.is_acked() {
+                        stats::bump(&stats.ack_failures);
+                    }
+                    let key = history::dm_key(&peer_identity);
+                    let own = history::Entry::new(
+                        &dm.id,
+                        &identity,
+                        &settings.name,
+                        dm.timestamp,
+                        &dm.content,
+                    );
+                    let body = record_own(&history, &documents, &key, &dm.message_type, own).await;
+                    let read =
+                        mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                    send_read_receipt(&node, &peer_identity, read);
+                    let ack = if status.is_acked() { "✓" } else { "?" };
+                    println!(
+                        "{} {} [{}]",
+                        ui::paint(ui::YELLOW, format!("[dm → {}]", &peer_identity[..8])),
+                        markup::render(&sanitize::text(&body)),
+                        ack
+                    );
+                }
+                Err(e) => {
+                    // Nothing was sent, so nothing went unacknowledged
+                    if !matches!(e, DeliveryError::TooLarge(_)) {
+                        stats::bump(&stats.ack_failures);
+                    }
+                    eprintln!("{} {e}", ui::paint(ui::RED, "[dm error]"));
+                }
+            }
+        }
+
+        if let Some(group_msg) = pending_room_message {
+            match publish_room_message(&node, &signer, &private_rooms, &active_room, &group_msg)
+                .await
+            {
+                Ok(lock) => {
+                    stats::bump(&stats.messages_sent);
+                    let own = history::Entry::new(
+                        &group_msg.id,
+                        &identity,
+                        &settings.name,
+                        group_msg.timestamp,
+                        &group_msg.content,
+                    );
+                    let body = record_own(
+                        &history,
+                        &documents,
+                        &history::room_key(&active_room),
+                        &group_msg.message_type,
+                        own,
+                    )
+                    .await;
+                    let key = history::room_key(&active_room);
+                    mark_conversation_read(&history, &read_markers, &key, &identity).await;
+                    let formatted =
+                        format!("{}{}@{}: {}", lock, settings.name, &identity[..8], body);
+                    let multi_room = joined_rooms.read().await.len() > 1;
+                    println!(
+                        "{} {}",
+                        ui::paint(ui::GREEN, room_label(&active_room, multi_room)),
+                        markup::render(&sanitize::text(&formatted))
+                    );
                 }
+                Err(e) => eprintln!("{e:#}"),
             }
         }
     }
 
+    // Remember who we were connected to so the next start can rejoin.
+    match bootstrap::save_known_peers(&node, &settings.known_peers_file).await {
+        Ok(n) if n > 0 => println!(
+            "Saved {n} bootstrap peers to {}",
+            settings.known_peers_file.display()
+        ),
+        Ok(_) => {}
+        Err(e) => eprintln!("{e:#}"),
+    }
+
     Ok(())
 }
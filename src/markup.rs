@@ -0,0 +1,266 @@
+//! Rich text rendering
+//!
+//! Renders a small, safe subset of markdown into ANSI styles: `**bold**`,
+//! `*italic*`, `` `code` ``, fenced code blocks, `[text](url)` links and
+//! bare `http(s)://` URLs. The input must already be sanitized; only the
+//! styles added here produce escape sequences. Links always show their
+//! target, so a peer cannot disguise where one leads.
+//!
+//! Rendering is skipped with `--output plain` or `rich_text = false`, in
+//! which case the text is shown exactly as sent.
+
+use std::sync::OnceLock;
+
+use crate::ui;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Farthest a closing delimiter or link part is looked for, in characters.
+/// Keeps a line full of unmatched `*`, `_` or `[` linear to render.
+const MAX_SPAN: usize = 256;
+
+/// SGR sequence that ends every style, as written by [`ui::paint`].
+const RESET: &str = "\x1b[0m";
+
+/// Turn rendering on or off. Only the first call has an effect.
+pub fn init(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(true) && ui::color_enabled()
+}
+
+/// Render sanitized `text`, line by line.
+pub fn render(text: &str) -> String {
+    render_with(text, &|plain| plain.to_string())
+}
+
+/// Render sanitized `text`, passing its prose through `decorate`, e.g. to
+/// highlight mentions. Code and URLs are never decorated.
+pub fn render_with(text: &str, decorate: &dyn Fn(&str) -> String) -> String {
+    if !enabled() {
+        return decorate(text);
+    }
+    let mut lines = Vec::new();
+    let mut in_block = false;
+    for line in text.split('\n') {
+        if line.trim_start().starts_with("```") {
+            in_block = !in_block;
+            continue;
+        }
+        if in_block {
+            lines.push(format!("  {}", ui::paint(ui::YELLOW, line)));
+        } else {
+            lines.push(inline(line, decorate));
+        }
+    }
+    lines.join("\n")
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Render the inline styles of one line.
+fn inline(line: &str, decorate: &dyn Fn(&str) -> String) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::with_capacity(line.len());
+    // Prose not yet decorated
+    let mut plain = String::new();
+    let flush = |out: &mut String, plain: &mut String| {
+        out.push_str(&decorate(plain));
+        plain.clear();
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = i.checked_sub(1).map(|p| chars[p]);
+        match c {
+            '\\' if i + 1 < chars.len() && "\\*_`[".contains(chars[i + 1]) => {
+                plain.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' => {
+                if let Some(end) = find(&chars, i + 1, "`") {
+                    let code: String = chars[i + 1..end].iter().collect();
+                    flush(&mut out, &mut plain);
+                    out.push_str(&ui::paint(ui::YELLOW, code));
+                    i = end + 1;
+                    continue;
+                }
+            }
+            '*' | '_' if chars.get(i + 1) == Some(&c) => {
+                let delimiter: String = [c, c].iter().collect();
+                if opens(&chars, i + 2, prev, c) {
+                    if let Some(end) = closing(&chars, i + 2, &delimiter, c) {
+                        let inner: String = chars[i + 2..end].iter().collect();
+                        flush(&mut out, &mut plain);
+                        out.push_str(&nest(ui::BOLD, &inline(&inner, decorate)));
+                        i = end + 2;
+                        continue;
+                    }
+                }
+            }
+            '*' | '_' if opens(&chars, i + 1, prev, c) => {
+                if let Some(end) = closing(&chars, i + 1, &c.to_string(), c) {
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    flush(&mut out, &mut plain);
+                    out.push_str(&nest(ui::ITALIC, &inline(&inner, decorate)));
+                    i = end + 1;
+                    continue;
+                }
+            }
+            '[' => {
+                if let Some((label, url, end)) = link(&chars, i) {
+                    flush(&mut out, &mut plain);
+                    out.push_str(&inline(&label, decorate));
+                    out.push_str(" <");
+                    out.push_str(&ui::paint(ui::UNDERLINE, url));
+                    out.push('>');
+                    i = end;
+                    continue;
+                }
+            }
+            'h' if !prev.is_some_and(is_word) && url_at(&chars, i) => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| c.is_whitespace())
+                    .map_or(chars.len(), |n| i + n);
+                let url: String = chars[i..end].iter().collect();
+                flush(&mut out, &mut plain);
+                out.push_str(&ui::paint(ui::UNDERLINE, url));
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        plain.push(c);
+        i += 1;
+    }
+    flush(&mut out, &mut plain);
+    out
+}
+
+/// Paint `inner` with `sgr`, restoring it after every reset that ends a
+/// style nested inside, so `**bold *it* more**` stays bold to the end.
+fn nest(sgr: &str, inner: &str) -> String {
+    let restore = format!("{RESET}\x1b[{sgr}m");
+    ui::paint(sgr, inner.replace(RESET, &restore))
+}
+
+/// Position of the next `pattern` at or after `from`, at most
+/// [`MAX_SPAN`] characters on.
+fn find(chars: &[char], from: usize, pattern: &str) -> Option<usize> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    (from..chars.len().min(from + MAX_SPAN)).find(|&i| chars[i..].starts_with(&pattern))
+}
+
+/// Whether a `marker` delimiter ending just before `inner_start` opens a
+/// span: it is followed by a non-space and, for `_`, not glued to a word.
+fn opens(chars: &[char], inner_start: usize, prev: Option<char>, marker: char) -> bool {
+    let next_ok = chars
+        .get(inner_start)
+        .is_some_and(|c| !c.is_whitespace() && *c != marker);
+    next_ok && !(marker == '_' && prev.is_some_and(is_word))
+}
+
+/// Closing `delimiter` for a span starting at `from`: preceded by a
+/// non-space and, for `_`, not followed by a word character.
+fn closing(chars: &[char], from: usize, delimiter: &str, marker: char) -> Option<usize> {
+    let delimiter: Vec<char> = delimiter.chars().collect();
+    let width = delimiter.len();
+    (from..chars.len().min(from + MAX_SPAN)).find(|&end| {
+        let before_ok = end > from && !chars[end - 1].is_whitespace();
+        let after_ok = !(marker == '_' && chars.get(end + width).copied().is_some_and(is_word));
+        chars[end..].starts_with(&delimiter) && before_ok && after_ok
+    })
+}
+
+/// `[label](url)` starting at `start`: the label, the URL and the index
+/// just past the closing parenthesis.
+fn link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let close = find(chars, start + 1, "](")?;
+    let end = find(chars, close + 2, ")")?;
+    let label: String = chars[start + 1..close].iter().collect();
+    let url: String = chars[close + 2..end].iter().collect();
+    if label.is_empty() || url.is_empty() || url.chars().any(char::is_whitespace) {
+        return None;
+    }
+    Some((label, url, end + 1))
+}
+
+fn url_at(chars: &[char], i: usize) -> bool {
+    let rest: String = chars[i..chars.len().min(i + 8)].iter().collect();
+    rest.starts_with("http://") || rest.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputMode;
+
+    fn force_color() {
+        ui::init(OutputMode::Color);
+        init(true);
+    }
+
+    #[test]
+    fn render_cases() {
+        force_color();
+        let cases = [
+            ("plain", "plain"),
+            ("**bold**", "\x1b[1mbold\x1b[0m"),
+            ("*it* and _it_", "\x1b[3mit\x1b[0m and \x1b[3mit\x1b[0m"),
+            ("`**code**`", "\x1b[33m**code**\x1b[0m"),
+            (
+                "**bold *it* more**",
+                "\x1b[1mbold \x1b[3mit\x1b[0m\x1b[1m more\x1b[0m",
+            ),
+            (
+                "*it `code` more*",
+                "\x1b[3mit \x1b[33mcode\x1b[0m\x1b[3m more\x1b[0m",
+            ),
+            (
+                "**a *b `c` d* e**",
+                "\x1b[1ma \x1b[3mb \x1b[33mc\x1b[0m\x1b[1m\x1b[3m d\x1b[0m\x1b[1m e\x1b[0m",
+            ),
+            (
+                "[**site**](https://e.com) after",
+                "\x1b[1msite\x1b[0m <\x1b[4mhttps://e.com\x1b[0m> after",
+            ),
+            ("snake_case_name", "snake_case_name"),
+            ("\\*not italic*", "*not italic*"),
+            ("* not italic*", "* not italic*"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(render(input), expected, "render({input:?})");
+        }
+    }
+
+    #[test]
+    fn decorations_nest_inside_styles() {
+        force_color();
+        let highlight = |text: &str| text.replace("@me", &ui::paint(ui::REVERSE, "@me"));
+        let cases = [
+            ("hi @me", "hi \x1b[7m@me\x1b[0m"),
+            (
+                "**hi @me there**",
+                "\x1b[1mhi \x1b[7m@me\x1b[0m\x1b[1m there\x1b[0m",
+            ),
+            (
+                "_hi @me there_",
+                "\x1b[3mhi \x1b[7m@me\x1b[0m\x1b[3m there\x1b[0m",
+            ),
+            ("`@me`", "\x1b[33m@me\x1b[0m"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                render_with(input, &highlight),
+                expected,
+                "render({input:?})"
+            );
+        }
+    }
+}
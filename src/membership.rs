@@ -0,0 +1,502 @@
+//! Private rooms and signed invitations
+//!
+//! A room becomes private when its creator issues the first invite. An
+//! invite binds `(group_id, creator_id, invitee_id, expires_at_ms)` under
+//! the creator's Ed25519 signature and is delivered as a `groupInvite` DM.
+//! On entering the room the invitee publishes it as a `groupJoin` message;
+//! members that know the room is private check it and only then show the
+//! newcomer's messages.
+//!
+//! A received invite must come from the creator it names and is only held
+//! as pending until the user accepts it with `/accept`; it can never turn
+//! a room we are already in into a private one.
+//!
+//! Which rooms are private, who created them, our own invites, the
+//! admitted members and the group keys (see [`crate::e2e`]) are persisted
+//! with owner-only permissions, so a key rotation after a restart still
+//! reaches every member.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::e2e::{self, GroupKey};
+use crate::invite;
+use crate::signing::{self, Signer};
+use crate::{current_timestamp_ms, GroupInvitePayload};
+
+/// Domain separator so invite signatures cannot be replayed as anything else.
+const INVITE_DOMAIN: &str = "six7-room-invite-v1";
+/// Lifetime of an invite when `/invite` is not given a TTL.
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(7 * 86400);
+/// Minimum gap between re-announcements of our own invite.
+pub const ANNOUNCE_INTERVAL_MS: i64 = 60_000;
+
+/// Signed permission for `invitee_id` to take part in `group_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomInvite {
+    pub group_id: String,
+    pub creator_id: String,
+    pub invitee_id: String,
+    pub expires_at_ms: i64,
+    /// Creator's Ed25519 signature (hex)
+    pub signature: String,
+}
+
+impl RoomInvite {
+    pub fn issue(signer: &Signer, group_id: &str, invitee_id: &str, ttl: Duration) -> Result<Self> {
+        let mut invite = Self {
+            group_id: group_id.to_string(),
+            creator_id: signer.identity(),
+            invitee_id: invitee_id.to_string(),
+            expires_at_ms: invite::expires_at(ttl)?,
+            signature: String::new(),
+        };
+        invite.signature = signer.sign_hex(&invite.signed_bytes());
+        Ok(invite)
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(&(
+            INVITE_DOMAIN,
+            &self.group_id,
+            &self.creator_id,
+            &self.invitee_id,
+            self.expires_at_ms,
+        ))
+        .expect("invite serialization is infallible")
+    }
+
+    /// Check the creator's signature and that the invite has not expired.
+    pub fn verify(&self) -> Result<()> {
+        signing::verify_hex(&self.creator_id, &self.signed_bytes(), &self.signature)
+            .context("Invite signature is invalid")?;
+        if self.expires_at_ms < current_timestamp_ms() {
+            anyhow::bail!("Invite has expired");
+        }
+        Ok(())
+    }
+
+    /// Extract the signed invite carried by a `groupInvite` payload.
+    pub fn from_payload(payload: &GroupInvitePayload) -> Option<Self> {
+        Some(Self {
+            group_id: payload.group_id.clone(),
+            creator_id: payload.creator_id.clone(),
+            invitee_id: payload.invitee_id.clone()?,
+            expires_at_ms: payload.expires_at_ms?,
+            signature: payload.signature.clone()?,
+        })
+    }
+}
+
+/// What we know about one private room.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrivateRoom {
+    pub creator_id: String,
+    /// Our own invite, when we are not the creator
+    pub own_invite: Option<RoomInvite>,
+    /// Group keys, oldest first; the last one is current
+    #[serde(default)]
+    pub keys: Vec<GroupKey>,
+    /// Admitted members and the invite that admitted them
+    #[serde(default)]
+    pub admitted: HashMap<String, RoomInvite>,
+    #[serde(skip)]
+    last_announce_ms: i64,
+}
+
+/// A received invite waiting for `/accept`, with the group key it carried.
+#[derive(Debug, Clone)]
+pub struct PendingInvite {
+    pub invite: RoomInvite,
+    pub key: Option<GroupKey>,
+}
+
+/// Private rooms keyed by group id, persisted as TOML.
+#[derive(Debug, Default)]
+pub struct PrivateRooms {
+    path: PathBuf,
+    rooms: BTreeMap<String, PrivateRoom>,
+    /// Invites received this session and not yet accepted
+    pending: BTreeMap<String, PendingInvite>,
+}
+
+pub type SharedPrivateRooms = Arc<RwLock<PrivateRooms>>;
+
+impl PrivateRooms {
+    pub fn load(path: &Path) -> Result<Self> {
+        let rooms = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid private rooms file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            rooms,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let text = toml::to_string_pretty(&self.rooms).context("Failed to encode rooms")?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&self.path)
+            .and_then(|mut f| std::io::Write::write_all(&mut f, text.as_bytes()))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn get(&self, group_id: &str) -> Option<&PrivateRoom> {
+        self.rooms.get(group_id)
+    }
+
+    pub fn is_private(&self, group_id: &str) -> bool {
+        self.rooms.contains_key(group_id)
+    }
+
+    /// Mark `group_id` as a private room created by us, generating its
+    /// first group key. Returns `false` if it is already private under
+    /// another creator.
+    pub fn create(&mut self, group_id: &str, my_identity: &str) -> bool {
+        let room = self
+            .rooms
+            .entry(group_id.to_string())
+            .or_insert_with(|| PrivateRoom {
+                creator_id: my_identity.to_string(),
+                ..PrivateRoom::default()
+            });
+        if room.creator_id != my_identity {
+            return false;
+        }
+        if room.keys.is_empty() {
+            room.keys.push(GroupKey::generate(1));
+        }
+        true
+    }
+
+    /// Key messages to `group_id` are encrypted with, if it has one.
+    pub fn current_key(&self, group_id: &str) -> Option<GroupKey> {
+        self.rooms.get(group_id)?.keys.last().cloned()
+    }
+
+    pub fn key_for(&self, group_id: &str, epoch: u32) -> Option<GroupKey> {
+        self.rooms
+            .get(group_id)?
+            .keys
+            .iter()
+            .find(|k| k.epoch == epoch)
+            .cloned()
+    }
+
+    /// Store a key received from the creator; older epochs beyond
+    /// [`e2e::RETAINED_EPOCHS`] are dropped. A key must start a newer epoch
+    /// than the current one.
+    pub fn install_key(&mut self, group_id: &str, key: GroupKey) -> Result<()> {
+        let room = self
+            .rooms
+            .get_mut(group_id)
+            .context("Room is not private")?;
+        if let Some(current) = room.keys.last().filter(|k| k.epoch >= key.epoch) {
+            anyhow::bail!(
+                "Key epoch {} is not newer than the current epoch {}",
+                key.epoch,
+                current.epoch
+            );
+        }
+        room.keys.push(key);
+        let excess = room.keys.len().saturating_sub(e2e::RETAINED_EPOCHS + 1);
+        room.keys.drain(..excess);
+        Ok(())
+    }
+
+    /// Start a new key epoch (creator only). Returns the new key.
+    pub fn rotate_key(&mut self, group_id: &str) -> Result<GroupKey> {
+        let epoch = match self.current_key(group_id) {
+            Some(k) => k.epoch.checked_add(1).context("Key epochs are exhausted")?,
+            None => 1,
+        };
+        let key = GroupKey::generate(epoch);
+        self.install_key(group_id, key.clone())?;
+        Ok(key)
+    }
+
+    /// Forget `member` until they announce a valid invite again.
+    pub fn remove_member(&mut self, group_id: &str, member: &str) -> bool {
+        self.rooms
+            .get_mut(group_id)
+            .is_some_and(|room| room.admitted.remove(member).is_some())
+    }
+
+    /// Drop everything we know about `group_id` (after leaving it).
+    pub fn forget(&mut self, group_id: &str) -> bool {
+        self.rooms.remove(group_id).is_some()
+    }
+
+    /// Check an invite `from` sent us and hold it until the user accepts
+    /// it. Only the creator named in the invite may send it.
+    pub fn offer_invite(
+        &mut self,
+        invite: RoomInvite,
+        key: Option<GroupKey>,
+        from: &str,
+        my_identity: &str,
+    ) -> Result<()> {
+        if invite.creator_id != from {
+            anyhow::bail!("Invite was not sent by the room's creator");
+        }
+        if invite.invitee_id != my_identity {
+            anyhow::bail!("Invite is for another identity");
+        }
+        invite.verify()?;
+        if self
+            .rooms
+            .get(&invite.group_id)
+            .is_some_and(|room| room.creator_id != invite.creator_id)
+        {
+            anyhow::bail!("Invite creator does not match the known room creator");
+        }
+        self.pending
+            .insert(invite.group_id.clone(), PendingInvite { invite, key });
+        Ok(())
+    }
+
+    /// Invites waiting for `/accept`, by room.
+    pub fn pending_invites(&self) -> impl Iterator<Item = &PendingInvite> {
+        self.pending.values()
+    }
+
+    /// Accept the pending invite to `group_id`: the room becomes private
+    /// under the invite's creator and the key it carried is installed. An
+    /// invite that fails its checks stays pending and nothing is changed.
+    pub fn accept_invite(&mut self, group_id: &str) -> Result<()> {
+        let PendingInvite { invite, key } = self
+            .pending
+            .get(group_id)
+            .with_context(|| format!("No pending invite to {group_id}"))?;
+        invite.verify()?;
+        let room = self.rooms.get(group_id);
+        if room.is_some_and(|room| room.creator_id != invite.creator_id) {
+            anyhow::bail!("Invite creator does not match the known room creator");
+        }
+        // A renewed invite carries the key we already hold
+        let key = key
+            .clone()
+            .filter(|k| self.key_for(group_id, k.epoch).as_ref() != Some(k));
+        if let (Some(key), Some(current)) = (&key, room.and_then(|room| room.keys.last())) {
+            if current.epoch >= key.epoch {
+                anyhow::bail!(
+                    "Key epoch {} is not newer than the current epoch {}",
+                    key.epoch,
+                    current.epoch
+                );
+            }
+        }
+
+        let PendingInvite { invite, .. } = self.pending.remove(group_id).expect("checked above");
+        let room = self
+            .rooms
+            .entry(group_id.to_string())
+            .or_insert_with(|| PrivateRoom {
+                creator_id: invite.creator_id.clone(),
+                ..PrivateRoom::default()
+            });
+        room.own_invite = Some(invite);
+        if let Some(key) = key {
+            self.install_key(group_id, key)?;
+        }
+        Ok(())
+    }
+
+    /// Whether messages from `sender` may be shown in `group_id`. Public
+    /// rooms admit everyone.
+    pub fn admits(&self, group_id: &str, sender: &str) -> bool {
+        match self.rooms.get(group_id) {
+            None => true,
+            Some(room) => {
+                room.creator_id == sender
+                    || room
+                        .admitted
+                        .get(sender)
+                        .is_some_and(|invite| invite.expires_at_ms >= current_timestamp_ms())
+            }
+        }
+    }
+
+    /// Admit `sender` on the strength of an announced invite. Returns
+    /// `Ok(true)` when the sender was not admitted before.
+    pub fn admit(&mut self, group_id: &str, sender: &str, invite: &RoomInvite) -> Result<bool> {
+        let room = self
+            .rooms
+            .get_mut(group_id)
+            .context("Room is not private")?;
+        if invite.group_id != group_id
+            || invite.invitee_id != sender
+            || invite.creator_id != room.creator_id
+        {
+            anyhow::bail!("Invite does not match sender and room");
+        }
+        invite.verify()?;
+        Ok(room
+            .admitted
+            .insert(sender.to_string(), invite.clone())
+            .is_none())
+    }
+
+    /// Our own invite for `group_id` if it is due to be (re-)announced.
+    pub fn take_announcement(&mut self, group_id: &str) -> Option<RoomInvite> {
+        let room = self.rooms.get_mut(group_id)?;
+        let invite = room.own_invite.clone()?;
+        let now = current_timestamp_ms();
+        if now - room.last_announce_ms < ANNOUNCE_INTERVAL_MS {
+            return None;
+        }
+        room.last_announce_ms = now;
+        Some(invite)
+    }
+
+    /// The invite `member` was admitted to `group_id` with.
+    pub fn admitted_invite(&self, group_id: &str, member: &str) -> Option<&RoomInvite> {
+        self.rooms.get(group_id)?.admitted.get(member)
+    }
+
+    /// Currently admitted members of `group_id`, including the creator.
+    pub fn members(&self, group_id: &str) -> Vec<String> {
+        let Some(room) = self.rooms.get(group_id) else {
+            return Vec::new();
+        };
+        let now = current_timestamp_ms();
+        let mut members = vec![room.creator_id.clone()];
+        members.extend(
+            room.admitted
+                .iter()
+                .filter(|(_, invite)| invite.expires_at_ms >= now)
+                .map(|(id, _)| id.clone()),
+        );
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "room";
+
+    fn invite_from(creator: &Signer, invitee: &str) -> RoomInvite {
+        RoomInvite::issue(creator, ROOM, invitee, DEFAULT_INVITE_TTL).unwrap()
+    }
+
+    /// Rooms holding `invite` as pending, as if offered by its creator.
+    fn pending(invite: RoomInvite, key: Option<GroupKey>) -> PrivateRooms {
+        let mut rooms = PrivateRooms::default();
+        rooms
+            .pending
+            .insert(invite.group_id.clone(), PendingInvite { invite, key });
+        rooms
+    }
+
+    #[test]
+    fn offer_checks_sender_and_invitee() {
+        let (creator, me) = (Signer::from_seed([1; 32]), Signer::from_seed([2; 32]));
+        let invite = invite_from(&creator, &me.identity());
+        let mut rooms = PrivateRooms::default();
+        assert!(rooms
+            .offer_invite(invite.clone(), None, &me.identity(), &me.identity())
+            .is_err());
+        assert!(rooms
+            .offer_invite(
+                invite.clone(),
+                None,
+                &creator.identity(),
+                &creator.identity()
+            )
+            .is_err());
+        assert_eq!(rooms.pending_invites().count(), 0);
+        rooms
+            .offer_invite(invite, None, &creator.identity(), &me.identity())
+            .unwrap();
+        assert_eq!(rooms.pending_invites().count(), 1);
+        assert!(!rooms.is_private(ROOM));
+    }
+
+    #[test]
+    fn accept_installs_room_and_key() {
+        let (creator, me) = (Signer::from_seed([1; 32]), Signer::from_seed([2; 32]));
+        let invite = invite_from(&creator, &me.identity());
+        let key = GroupKey::generate(1);
+        let mut rooms = pending(invite.clone(), Some(key.clone()));
+        rooms.accept_invite(ROOM).unwrap();
+        let room = rooms.get(ROOM).unwrap();
+        assert_eq!(room.creator_id, creator.identity());
+        assert_eq!(room.own_invite, Some(invite.clone()));
+        assert_eq!(rooms.current_key(ROOM), Some(key.clone()));
+        assert_eq!(rooms.pending_invites().count(), 0);
+        assert!(rooms.accept_invite(ROOM).is_err());
+
+        // A renewed invite carrying the key we hold is accepted
+        rooms.pending.insert(
+            ROOM.to_string(),
+            PendingInvite {
+                invite,
+                key: Some(key),
+            },
+        );
+        rooms.accept_invite(ROOM).unwrap();
+        assert_eq!(rooms.get(ROOM).unwrap().keys.len(), 1);
+    }
+
+    #[test]
+    fn refused_accept_changes_nothing() {
+        let (creator, other, me) = (
+            Signer::from_seed([1; 32]),
+            Signer::from_seed([3; 32]),
+            Signer::from_seed([2; 32]),
+        );
+        let mut expired = invite_from(&creator, &me.identity());
+        expired.expires_at_ms = current_timestamp_ms() - 1;
+        expired.signature = creator.sign_hex(&expired.signed_bytes());
+        let cases = [
+            // invite, key, known room creator and key epoch
+            (expired, None, None),
+            (
+                invite_from(&creator, &me.identity()),
+                None,
+                Some((&other, 1)),
+            ),
+            (
+                invite_from(&creator, &me.identity()),
+                Some(GroupKey::generate(1)),
+                Some((&creator, 2)),
+            ),
+        ];
+        for (invite, key, known) in cases {
+            let mut rooms = pending(invite.clone(), key);
+            if let Some((known_creator, epoch)) = known {
+                rooms.create(ROOM, &known_creator.identity());
+                rooms.rooms.get_mut(ROOM).unwrap().keys = vec![GroupKey::generate(epoch)];
+            }
+            let keys_before = rooms.get(ROOM).map(|room| room.keys.clone());
+            assert!(rooms.accept_invite(ROOM).is_err(), "{invite:?}");
+            assert_eq!(rooms.pending_invites().count(), 1);
+            assert_eq!(rooms.is_private(ROOM), known.is_some());
+            if let Some(room) = rooms.get(ROOM) {
+                assert_eq!(room.own_invite, None);
+                assert_eq!(Some(&room.keys), keys_before.as_ref());
+            }
+        }
+    }
+}
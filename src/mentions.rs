@@ -0,0 +1,101 @@
+//! @mentions in room messages
+//!
+//! A mention is `@` followed by a peer's name or by at least
+//! [`MIN_PREFIX`] hex digits of their identity, at the start of the text or
+//! after a character that cannot be part of a word. Mentions are resolved
+//! against the peer directory when a line is shown: mentions of us are
+//! highlighted (and can fire the `mention` notification), mentions of known
+//! peers are colored, anything else is left alone.
+
+use std::collections::HashMap;
+
+use crate::ui;
+
+/// Shortest identity prefix accepted as a mention.
+pub const MIN_PREFIX: usize = 4;
+
+enum Target {
+    Me,
+    Peer,
+    Unknown,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Byte ranges of the mention tokens in `text`, without the `@`.
+fn tokens(text: &str) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    let mut prev = None;
+    for (i, c) in text.char_indices() {
+        if c == '@' && !prev.is_some_and(is_word) {
+            let start = i + c.len_utf8();
+            let rest = &text[start..];
+            let len = rest.find(|c: char| !is_word(c)).unwrap_or(rest.len());
+            // A trailing dot ends the sentence, not the name
+            let token = rest[..len].trim_end_matches('.');
+            if !token.is_empty() {
+                found.push((start, start + token.len()));
+            }
+        }
+        prev = Some(c);
+    }
+    found
+}
+
+/// Who we are and who we know, for resolving mentions.
+pub struct Directory<'a> {
+    pub my_identity: &'a str,
+    pub my_name: &'a str,
+    /// Known peers: identity to name
+    pub peers: &'a HashMap<String, String>,
+}
+
+impl Directory<'_> {
+    fn resolve(&self, token: &str) -> Target {
+        let lower = token.to_lowercase();
+        let is_prefix = |identity: &str| {
+            token.len() >= MIN_PREFIX
+                && token.chars().all(|c| c.is_ascii_hexdigit())
+                && identity.starts_with(&lower)
+        };
+        if token.eq_ignore_ascii_case(self.my_name) || is_prefix(self.my_identity) {
+            Target::Me
+        } else if self
+            .peers
+            .iter()
+            .any(|(id, name)| name.eq_ignore_ascii_case(token) || is_prefix(id))
+        {
+            Target::Peer
+        } else {
+            Target::Unknown
+        }
+    }
+
+    /// Whether `text` mentions us.
+    pub fn mentions_me(&self, text: &str) -> bool {
+        tokens(text)
+            .into_iter()
+            .any(|(start, end)| matches!(self.resolve(&text[start..end]), Target::Me))
+    }
+
+    /// Color the mentions in `text`, which must already be sanitized.
+    pub fn highlight(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut copied = 0;
+        for (start, end) in tokens(text) {
+            let mention = &text[start - 1..end];
+            let painted = match self.resolve(&text[start..end]) {
+                Target::Me => ui::paint(ui::REVERSE, mention),
+                Target::Peer => ui::paint(ui::CYAN, mention),
+                Target::Unknown => continue,
+            };
+            out.push_str(&text[copied..start - 1]);
+            out.push_str(&painted);
+            copied = end;
+        }
+        out.push_str(&text[copied..]);
+        out
+    }
+}
@@ -0,0 +1,449 @@
+//! Prometheus metrics endpoint
+//!
+//! With `--metrics-addr` (or `metrics_addr` in a profile) six7 serves
+//! `GET /metrics` over plain HTTP in the Prometheus text format: the fields
+//! of `node.telemetry()` that `/telemetry` prints, plus the six7 counters
+//! from [`crate::stats`]. The endpoint has no authentication, so bind it to
+//! localhost unless the network in between is trusted.
+
+use std::fmt::{Display, Write as _};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use korium::Node;
+
+use crate::stats::{self, SharedStats, Stats};
+
+/// Longest request head read before giving up on a client.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+/// Time a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause after a failed `accept`, doubled while failures persist (for
+/// example when we run out of file descriptors) up to the maximum.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Bind `addr` and serve metrics in the background. Returns the bound
+/// address, which differs from `addr` when it asked for port 0.
+pub async fn spawn(addr: SocketAddr, node: Arc<Node>, stats: SharedStats) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics endpoint {addr}"))?;
+    let local = listener.local_addr()?;
+    tokio::spawn(serve(listener, move || {
+        let node = node.clone();
+        let stats = stats.clone();
+        async move { render(&node, &stats).await }
+    }));
+    Ok(local)
+}
+
+/// Answer connections on `listener`, calling `metrics` for the body of
+/// each `GET /metrics`.
+async fn serve<F, Fut>(listener: TcpListener, metrics: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = String> + Send,
+{
+    let metrics = Arc::new(metrics);
+    let mut backoff = ACCEPT_BACKOFF;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => {
+                backoff = ACCEPT_BACKOFF;
+                conn
+            }
+            Err(e) => {
+                tracing::warn!("metrics accept failed: {e}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, metrics.as_ref()).await {
+                tracing::debug!("metrics request from {peer} failed: {e:#}");
+            }
+        });
+    }
+}
+
+/// What to answer a request with.
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Metrics,
+    /// Status line and body of an error response
+    Error(&'static str, &'static str),
+}
+
+/// Route a request by the first line of its `head`.
+fn route(head: &str) -> Route {
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or("");
+    match (method, path) {
+        ("GET", "/metrics") => Route::Metrics,
+        ("GET", _) => Route::Error("404 Not Found", "Not found; try /metrics\n"),
+        _ => Route::Error("405 Method Not Allowed", "Only GET is supported\n"),
+    }
+}
+
+/// A complete HTTP response closing the connection.
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        body.len()
+    )
+}
+
+async fn handle<F, Fut>(mut stream: TcpStream, metrics: &F) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+{
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .context("Request timed out")??;
+    let response = match route(&head) {
+        Route::Metrics => response("200 OK", &metrics().await),
+        Route::Error(status, body) => response(status, body),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read up to the blank line that ends the request head.
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_BYTES {
+            anyhow::bail!("Request head too large");
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// One metric family in the text format.
+struct Family<'a> {
+    out: &'a mut String,
+    name: &'static str,
+}
+
+impl<'a> Family<'a> {
+    fn new(out: &'a mut String, name: &'static str, kind: &str, help: &str) -> Self {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        Self { out, name }
+    }
+
+    fn sample(&mut self, value: impl Display) -> &mut Self {
+        let _ = writeln!(self.out, "{} {value}", self.name);
+        self
+    }
+
+    fn labelled(&mut self, label: &str, key: impl Display, value: impl Display) -> &mut Self {
+        let _ = writeln!(self.out, "{}{{{label}=\"{key}\"}} {value}", self.name);
+        self
+    }
+}
+
+/// All metrics, in the Prometheus text exposition format.
+async fn render(node: &Node, stats: &Stats) -> String {
+    let t = node.telemetry().await;
+    let mut out = String::new();
+    let o = &mut out;
+
+    Family::new(
+        o,
+        "six7_dht_stored_keys",
+        "gauge",
+        "Keys held in the local DHT store",
+    )
+    .sample(t.stored_keys);
+    Family::new(
+        o,
+        "six7_dht_replication_factor",
+        "gauge",
+        "DHT replication factor",
+    )
+    .sample(t.replication_factor);
+    Family::new(o, "six7_dht_concurrency", "gauge", "DHT lookup concurrency").sample(t.concurrency);
+    Family::new(
+        o,
+        "six7_dht_pressure",
+        "gauge",
+        "DHT store pressure (0 to 1)",
+    )
+    .sample(t.pressure);
+    Family::new(
+        o,
+        "six7_routing_peers",
+        "gauge",
+        "Peers in the routing table",
+    )
+    .sample(t.connected_peers);
+    Family::new(
+        o,
+        "six7_gossipsub_mesh_peers",
+        "gauge",
+        "Peers in the gossipsub mesh",
+    )
+    .sample(t.gossipsub_mesh_peers);
+    Family::new(
+        o,
+        "six7_gossipsub_topics",
+        "gauge",
+        "Subscribed gossipsub topics",
+    )
+    .sample(t.gossipsub_topics);
+    Family::new(
+        o,
+        "six7_transport_requests_sent_total",
+        "counter",
+        "Transport requests sent",
+    )
+    .sample(t.transport_requests_sent);
+    Family::new(
+        o,
+        "six7_transport_requests_received_total",
+        "counter",
+        "Transport requests received",
+    )
+    .sample(t.transport_requests_received);
+    Family::new(
+        o,
+        "six7_transport_responses_success_total",
+        "counter",
+        "Transport requests answered successfully",
+    )
+    .sample(t.transport_responses_success);
+    Family::new(
+        o,
+        "six7_transport_errors_total",
+        "counter",
+        "Transport errors",
+    )
+    .sample(t.transport_errors);
+    Family::new(
+        o,
+        "six7_transport_connections_cached",
+        "gauge",
+        "Cached transport connections",
+    )
+    .sample(t.transport_connections_cached);
+    Family::new(
+        o,
+        "six7_transport_connections_established_total",
+        "counter",
+        "Transport connections established",
+    )
+    .sample(t.transport_connections_established);
+    Family::new(
+        o,
+        "six7_tunnel_active_sessions",
+        "gauge",
+        "Active relay tunnel sessions",
+    )
+    .sample(t.tunnel_active_sessions);
+    Family::new(
+        o,
+        "six7_tunnel_sessions_total",
+        "counter",
+        "Relay tunnel sessions",
+    )
+    .sample(t.tunnel_total_sessions);
+    Family::new(
+        o,
+        "six7_tunnel_relayed_bytes_total",
+        "counter",
+        "Bytes relayed through tunnels",
+    )
+    .labelled("direction", "ingress", t.tunnel_bytes_relayed_ingress)
+    .labelled("direction", "egress", t.tunnel_bytes_relayed_egress);
+
+    let mut centroids = Family::new(
+        o,
+        "six7_latency_tier_centroid_ms",
+        "gauge",
+        "Centroid of each latency tier in milliseconds",
+    );
+    for (tier, centroid) in t.tier_centroids.iter().enumerate() {
+        centroids.labelled("tier", tier, centroid);
+    }
+    let mut counts = Family::new(
+        o,
+        "six7_latency_tier_peers",
+        "gauge",
+        "Peers in each latency tier",
+    );
+    for (tier, count) in t.tier_counts.iter().enumerate() {
+        counts.labelled("tier", tier, count);
+    }
+
+    render_stats(o, stats);
+    out
+}
+
+/// The six7 counters, in the Prometheus text exposition format.
+fn render_stats(o: &mut String, stats: &Stats) {
+    Family::new(
+        o,
+        "six7_messages_sent_total",
+        "counter",
+        "Room messages published and DMs delivered",
+    )
+    .sample(stats::get(&stats.messages_sent));
+    Family::new(
+        o,
+        "six7_messages_received_total",
+        "counter",
+        "Room messages and DMs received from peers",
+    )
+    .sample(stats::get(&stats.messages_received));
+    Family::new(
+        o,
+        "six7_messages_dropped_total",
+        "counter",
+        "Received messages discarded unseen",
+    )
+    .sample(stats::get(&stats.messages_dropped));
+    Family::new(
+        o,
+        "six7_dm_ack_failures_total",
+        "counter",
+        "Sent DMs the peer did not acknowledge",
+    )
+    .sample(stats::get(&stats.ack_failures));
+    Family::new(
+        o,
+        "six7_room_messages_total",
+        "counter",
+        "Room messages by sender signature",
+    )
+    .labelled("signature", "verified", stats::get(&stats.room_signed))
+    .labelled("signature", "unsigned", stats::get(&stats.room_unsigned))
+    .labelled(
+        "signature",
+        "invalid",
+        stats::get(&stats.room_bad_signature),
+    );
+    Family::new(
+        o,
+        "six7_throttled_total",
+        "counter",
+        "Messages refused by the per-peer rate limit",
+    )
+    .labelled("kind", "room", stats::get(&stats.room_throttled))
+    .labelled("kind", "dm", stats::get(&stats.dm_throttled));
+    Family::new(
+        o,
+        "six7_auto_mutes_total",
+        "counter",
+        "Peers muted automatically for flooding",
+    )
+    .sample(stats::get(&stats.auto_mutes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_cases() {
+        let cases = [
+            ("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n", Route::Metrics),
+            ("GET /metrics?x=1 HTTP/1.1\r\n\r\n", Route::Metrics),
+            ("GET / HTTP/1.1\r\n\r\n", Route::Error("404 Not Found", "")),
+            (
+                "POST /metrics HTTP/1.1\r\n\r\n",
+                Route::Error("405 Method Not Allowed", ""),
+            ),
+            ("", Route::Error("405 Method Not Allowed", "")),
+        ];
+        for (head, expected) in cases {
+            let got = match route(head) {
+                Route::Error(status, _) => Route::Error(status, ""),
+                metrics => metrics,
+            };
+            assert_eq!(got, expected, "route({head:?})");
+        }
+    }
+
+    #[test]
+    fn stats_render_as_counters() {
+        let stats = Stats::default();
+        stats::bump(&stats.messages_received);
+        stats::bump(&stats.messages_received);
+        stats::bump(&stats.dm_throttled);
+        let mut out = String::new();
+        render_stats(&mut out, &stats);
+        for line in [
+            "# TYPE six7_messages_received_total counter",
+            "six7_messages_received_total 2",
+            "six7_messages_dropped_total 0",
+            "six7_throttled_total{kind=\"dm\"} 1",
+            "six7_room_messages_total{signature=\"verified\"} 0",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {line:?} in\n{out}");
+        }
+    }
+
+    /// Send `request` to `addr` and return the whole response.
+    async fn fetch(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn endpoint_serves_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats: SharedStats = Arc::default();
+        stats::bump(&stats.messages_sent);
+        let served = stats.clone();
+        tokio::spawn(serve(listener, move || {
+            let stats = served.clone();
+            async move {
+                let mut out = String::new();
+                render_stats(&mut out, &stats);
+                out
+            }
+        }));
+
+        let response = fetch(addr, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.lines();
+        assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+        let headers: Vec<&str> = lines.collect();
+        assert!(headers.contains(&"Content-Type: text/plain; version=0.0.4; charset=utf-8"));
+        assert!(headers.contains(&format!("Content-Length: {}", body.len()).as_str()));
+        assert!(headers.contains(&"Connection: close"));
+        assert!(body.lines().any(|l| l == "six7_messages_sent_total 1"));
+
+        let response = fetch(addr, "DELETE /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,425 @@
+//! Moderation of private rooms
+//!
+//! The creator of a private room owns it and may promote members to
+//! moderators. Owners and moderators issue signed actions (kick, ban,
+//! mute, ...) that are published to the room as `moderation` messages;
+//! every member's client checks the signature and the issuer's role
+//! before honoring one, and keeps the accepted actions in a per-group log.
+//! The current roles are derived by replaying that log in
+//! `(issued_at, signature)` order, re-checking each action's authority as
+//! it goes, so every client reaches the same roles whatever order the
+//! actions arrived in.
+//!
+//! Only the owner may promote or demote, and nobody can act against the
+//! owner or, unless they are the owner, against another moderator. Since
+//! issuers choose their own timestamps, an action is only accepted when it
+//! is dated within [`MAX_CLOCK_SKEW`] of now and its issuer holds the role
+//! it needs both at that time and under the current roles; a demoted
+//! moderator cannot backdate an action to before their demotion.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::current_timestamp_ms;
+use crate::signing::{self, Signer};
+
+/// Domain separator for moderation signatures.
+const MODERATION_DOMAIN: &str = "six7-moderation-v1";
+/// Mute length when `/mute member` is not given a duration.
+pub const DEFAULT_MUTE: Duration = Duration::from_secs(3600);
+/// How far an action's timestamp may be from our clock.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ModKind {
+    /// Remove from the room and revoke the invite they joined with
+    Kick,
+    /// Refuse the member whatever invite they present
+    Ban,
+    Unban,
+    /// Hide the member's messages until `until_ms`
+    Mute,
+    Unmute,
+    /// Make the member a moderator (owner only)
+    Promote,
+    Demote,
+}
+
+impl std::fmt::Display for ModKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = match self {
+            ModKind::Kick => "kicked",
+            ModKind::Ban => "banned",
+            ModKind::Unban => "unbanned",
+            ModKind::Mute => "muted",
+            ModKind::Unmute => "unmuted",
+            ModKind::Promote => "promoted",
+            ModKind::Demote => "demoted",
+        };
+        write!(f, "{verb}")
+    }
+}
+
+/// A signed moderation action (content of a `moderation` message, JSON).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModAction {
+    pub group_id: String,
+    pub kind: ModKind,
+    pub target: String,
+    pub issuer: String,
+    pub issued_at_ms: i64,
+    /// End of a mute (Unix epoch milliseconds)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until_ms: Option<i64>,
+    /// Signature of the invite a kicked member was admitted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_invite: Option<String>,
+    #[serde(default)]
+    pub reason: String,
+    /// Issuer's Ed25519 signature (hex)
+    pub signature: String,
+}
+
+impl ModAction {
+    pub fn issue(
+        signer: &Signer,
+        group_id: &str,
+        kind: ModKind,
+        target: &str,
+        until_ms: Option<i64>,
+        revoked_invite: Option<String>,
+        reason: &str,
+    ) -> Self {
+        let mut action = Self {
+            group_id: group_id.to_string(),
+            kind,
+            target: target.to_string(),
+            issuer: signer.identity(),
+            issued_at_ms: current_timestamp_ms(),
+            until_ms,
+            revoked_invite,
+            reason: reason.to_string(),
+            signature: String::new(),
+        };
+        action.signature = signer.sign_hex(&action.signed_bytes());
+        action
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        postcard::to_allocvec(&(
+            MODERATION_DOMAIN,
+            &self.group_id,
+            self.kind,
+            &self.target,
+            &self.issuer,
+            self.issued_at_ms,
+            self.until_ms,
+            &self.revoked_invite,
+            &self.reason,
+        ))
+        .expect("moderation serialization is infallible")
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        signing::verify_hex(&self.issuer, &self.signed_bytes(), &self.signature)
+            .context("Moderation signature is invalid")
+    }
+
+    /// Position of the action in its room's log.
+    fn order(&self) -> (i64, &str) {
+        (self.issued_at_ms, &self.signature)
+    }
+}
+
+/// Roles and sanctions in one room, derived from its log.
+#[derive(Debug, Default)]
+pub struct RoomRoles {
+    pub moderators: BTreeSet<String>,
+    pub banned: BTreeSet<String>,
+    /// Muted members and when the mute ends
+    pub muted: HashMap<String, i64>,
+    /// Signatures of invites revoked by kicks
+    pub revoked_invites: BTreeSet<String>,
+}
+
+impl RoomRoles {
+    /// Roles after `actions`, which are in log order. Actions their issuer
+    /// had no authority for at that point are skipped.
+    fn replay(actions: &[ModAction], owner: &str) -> Self {
+        let mut roles = Self::default();
+        for action in actions {
+            if roles
+                .authorize(owner, &action.issuer, action.kind, &action.target)
+                .is_ok()
+            {
+                roles.apply(action);
+            }
+        }
+        roles
+    }
+
+    fn apply(&mut self, action: &ModAction) {
+        let target = action.target.clone();
+        match action.kind {
+            ModKind::Kick => {
+                self.revoked_invites
+                    .extend(action.revoked_invite.iter().cloned());
+            }
+            ModKind::Ban => {
+                self.banned.insert(target);
+            }
+            ModKind::Unban => {
+                self.banned.remove(&target);
+            }
+            ModKind::Mute => {
+                self.muted
+                    .insert(target, action.until_ms.unwrap_or(i64::MAX));
+            }
+            ModKind::Unmute => {
+                self.muted.remove(&target);
+            }
+            ModKind::Promote => {
+                self.moderators.insert(target);
+            }
+            ModKind::Demote => {
+                self.moderators.remove(&target);
+            }
+        }
+    }
+
+    pub fn is_muted(&self, member: &str) -> bool {
+        self.muted
+            .get(member)
+            .is_some_and(|until| *until > current_timestamp_ms())
+    }
+
+    /// Check that `issuer` may take `kind` against `target` in a room
+    /// owned by `owner`.
+    fn authorize(&self, owner: &str, issuer: &str, kind: ModKind, target: &str) -> Result<()> {
+        let is_owner = issuer == owner;
+        if !is_owner && !self.moderators.contains(issuer) {
+            anyhow::bail!("{} is not a moderator", &issuer[..8.min(issuer.len())]);
+        }
+        if matches!(kind, ModKind::Promote | ModKind::Demote) && !is_owner {
+            anyhow::bail!("Only the room owner can change moderators");
+        }
+        if target == owner {
+            anyhow::bail!("The room owner cannot be moderated");
+        }
+        if !is_owner && self.moderators.contains(target) {
+            anyhow::bail!("Only the room owner can act against a moderator");
+        }
+        Ok(())
+    }
+}
+
+/// Accepted moderation actions per group, persisted as TOML.
+#[derive(Debug, Default)]
+pub struct ModerationLog {
+    path: PathBuf,
+    groups: BTreeMap<String, Vec<ModAction>>,
+}
+
+pub type SharedModerationLog = Arc<RwLock<ModerationLog>>;
+
+impl ModerationLog {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut groups: BTreeMap<String, Vec<ModAction>> = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid moderation log {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        // Logs written before they were kept in order
+        for log in groups.values_mut() {
+            log.sort_by(|a, b| a.order().cmp(&b.order()));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            groups,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let text =
+            toml::to_string_pretty(&self.groups).context("Failed to encode moderation log")?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Roles in `group_id`, a room owned by `owner`.
+    pub fn roles(&self, group_id: &str, owner: &str) -> RoomRoles {
+        self.groups
+            .get(group_id)
+            .map(|log| RoomRoles::replay(log, owner))
+            .unwrap_or_default()
+    }
+
+    pub fn log(&self, group_id: &str) -> &[ModAction] {
+        self.groups.get(group_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Verify `action` against the roles in the room owned by `owner`, both
+    /// now and at the time it was issued, and insert it in log order.
+    /// Returns `Ok(false)` for an action already in the log.
+    pub fn apply(&mut self, action: ModAction, owner: &str) -> Result<bool> {
+        self.apply_at(action, owner, current_timestamp_ms())
+    }
+
+    fn apply_at(&mut self, action: ModAction, owner: &str, now_ms: i64) -> Result<bool> {
+        action.verify()?;
+        if action.issued_at_ms.abs_diff(now_ms) > MAX_CLOCK_SKEW.as_millis() as u64 {
+            anyhow::bail!("Moderation action is dated too far from now");
+        }
+        let log = self.groups.entry(action.group_id.clone()).or_default();
+        if log.iter().any(|a| a.signature == action.signature) {
+            return Ok(false);
+        }
+        let at = log.partition_point(|a| a.order() < action.order());
+        for roles in [
+            RoomRoles::replay(log, owner),
+            RoomRoles::replay(&log[..at], owner),
+        ] {
+            roles.authorize(owner, &action.issuer, action.kind, &action.target)?;
+        }
+        log.insert(at, action);
+        Ok(true)
+    }
+
+    /// Drop the log of a room we left.
+    pub fn forget(&mut self, group_id: &str) {
+        self.groups.remove(group_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "room";
+    const NOW: i64 = 1_700_000_000_000;
+    const MINUTE: i64 = 60_000;
+
+    fn signer(n: u8) -> Signer {
+        Signer::from_seed([n; 32])
+    }
+
+    /// An action by `issuer` against `target`, signed and dated `at`.
+    fn action(issuer: &Signer, kind: ModKind, target: &Signer, at: i64) -> ModAction {
+        let mut action = ModAction {
+            group_id: ROOM.to_string(),
+            kind,
+            target: target.identity(),
+            issuer: issuer.identity(),
+            issued_at_ms: at,
+            until_ms: None,
+            revoked_invite: None,
+            reason: String::new(),
+            signature: String::new(),
+        };
+        action.signature = issuer.sign_hex(&action.signed_bytes());
+        action
+    }
+
+    #[test]
+    fn authorize_cases() {
+        let (owner, moderator, other_moderator, member) = ("owner", "mod", "mod2", "member");
+        let roles = RoomRoles {
+            moderators: [moderator, other_moderator].map(String::from).into(),
+            ..RoomRoles::default()
+        };
+        let cases = [
+            // issuer, kind, target, allowed
+            (owner, ModKind::Ban, member, true),
+            (owner, ModKind::Kick, moderator, true),
+            (owner, ModKind::Promote, member, true),
+            (owner, ModKind::Demote, moderator, true),
+            (moderator, ModKind::Ban, member, true),
+            (moderator, ModKind::Mute, member, true),
+            (moderator, ModKind::Promote, member, false),
+            (moderator, ModKind::Demote, other_moderator, false),
+            (moderator, ModKind::Kick, other_moderator, false),
+            (moderator, ModKind::Ban, owner, false),
+            (owner, ModKind::Demote, owner, false),
+            (member, ModKind::Kick, member, false),
+            (member, ModKind::Promote, member, false),
+        ];
+        for (issuer, kind, target, allowed) in cases {
+            assert_eq!(
+                roles.authorize(owner, issuer, kind, target).is_ok(),
+                allowed,
+                "{issuer} {kind} {target}"
+            );
+        }
+    }
+
+    #[test]
+    fn replay_skips_actions_without_authority() {
+        let (owner, moderator, member) = (signer(1), signer(2), signer(3));
+        let log = [
+            action(&moderator, ModKind::Ban, &member, NOW),
+            action(&owner, ModKind::Promote, &moderator, NOW + 1),
+            action(&moderator, ModKind::Mute, &member, NOW + 2),
+            action(&owner, ModKind::Demote, &moderator, NOW + 3),
+            action(&moderator, ModKind::Ban, &member, NOW + 4),
+        ];
+        let roles = RoomRoles::replay(&log, &owner.identity());
+        assert!(roles.moderators.is_empty());
+        assert!(roles.banned.is_empty());
+        assert!(roles.muted.contains_key(&member.identity()));
+    }
+
+    #[test]
+    fn apply_cases() {
+        let (owner, moderator, member) = (signer(1), signer(2), signer(3));
+        let mut log = ModerationLog::default();
+        let apply = |log: &mut ModerationLog, action: ModAction| {
+            log.apply_at(action, &owner.identity(), NOW)
+        };
+
+        let promote = action(&owner, ModKind::Promote, &moderator, NOW - 3 * MINUTE);
+        assert!(apply(&mut log, promote.clone()).unwrap());
+        // The same action again is a duplicate, not an error
+        assert!(!apply(&mut log, promote).unwrap());
+        assert!(apply(
+            &mut log,
+            action(&owner, ModKind::Demote, &moderator, NOW - MINUTE)
+        )
+        .unwrap());
+
+        let refused = [
+            // Backdated to when the moderator still had the role
+            action(&moderator, ModKind::Ban, &member, NOW - 2 * MINUTE),
+            // Dated outside the clock skew window
+            action(&owner, ModKind::Ban, &member, NOW - 10 * MINUTE),
+            action(&owner, ModKind::Ban, &member, NOW + 10 * MINUTE),
+            // Signed by someone else
+            ModAction {
+                issuer: owner.identity(),
+                ..action(&moderator, ModKind::Ban, &member, NOW)
+            },
+        ];
+        for action in refused {
+            assert!(apply(&mut log, action.clone()).is_err(), "{action:?}");
+        }
+        assert_eq!(log.log(ROOM).len(), 2);
+        assert!(log.roles(ROOM, &owner.identity()).banned.is_empty());
+
+        assert!(apply(&mut log, action(&owner, ModKind::Ban, &member, NOW)).unwrap());
+        let roles = log.roles(ROOM, &owner.identity());
+        assert!(roles.banned.contains(&member.identity()));
+        assert!(roles.moderators.is_empty());
+    }
+}
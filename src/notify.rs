@@ -0,0 +1,47 @@
+//! Local notification hooks
+//!
+//! Rings the terminal bell and/or runs the configured shell command. The
+//! event is passed to the command through environment variables rather than
+//! interpolated into the command line, so message text cannot inject shell
+//! syntax.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::NotifySettings;
+
+/// Fire the configured notification for an event of `kind` (`dm` or
+/// `mention`), unless that kind is switched off.
+pub fn notify(settings: &NotifySettings, kind: &str, from: &str, text: &str) {
+    let wanted = match kind {
+        "dm" => settings.dm,
+        "mention" => settings.mention,
+        _ => true,
+    };
+    if !wanted {
+        return;
+    }
+    if settings.bell {
+        print!("\x07");
+        std::io::stdout().flush().ok();
+    }
+    if let Some(ref command) = settings.command {
+        let spawned = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("SIX7_KIND", kind)
+            .env("SIX7_FROM", from)
+            .env("SIX7_TEXT", text)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            // Reap in the background so the hook never blocks the chat.
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => tracing::warn!("notify command failed: {e}"),
+        }
+    }
+}
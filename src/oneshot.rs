@@ -0,0 +1,186 @@
+//! Non-interactive one-shot subcommands
+//!
+//! `send-dm`, `publish` and `listen` join the network, perform a single
+//! action and exit, so six7 can be driven from cron jobs and CI.
+//!
+//! Exit codes:
+//!
+//! | Code | Meaning                                        |
+//! |------|------------------------------------------------|
+//! | 0    | Success                                        |
+//! | 1    | Error (invalid input, bootstrap failure, ...)  |
+//! | 2    | Usage error (reported by clap)                 |
+//! | 3    | Peer answered without acknowledging the DM     |
+//! | 4    | Peer unreachable (the send itself failed)      |
+//! | 5    | Timed out: no answer to the DM, or `--timeout` |
+
+use std::io::Read;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use korium::Node;
+
+use crate::config::Settings;
+use crate::signing::{self, Authenticity, Signer};
+use crate::{
+    bootstrap_node, check_encoded_size, decode_room_message, room_topic, send_direct, start_node,
+    validate_identity, AckStatus, DeliveryError, DirectMessage, GroupMessage,
+    MAX_MESSAGE_SIZE_BYTES,
+};
+
+pub const EXIT_NOT_ACKED: u8 = 3;
+pub const EXIT_UNREACHABLE: u8 = 4;
+pub const EXIT_TIMEOUT: u8 = 5;
+
+/// Start a node and join the network for a one-shot action. Called only
+/// after the action's input has been checked, so bad input fails fast.
+async fn connect(settings: &Settings) -> Result<Arc<Node>> {
+    let node = start_node(settings).await?;
+    if !bootstrap_node(&node, settings, |r| eprintln!("{r}")).await? {
+        eprintln!("warning: no --join or --bootstrap given; this node has no peers");
+    }
+    Ok(node)
+}
+
+/// `send-dm`: deliver one DM and map the peer's response to an exit code.
+pub async fn send_dm(settings: &Settings, to: &str, message: Option<String>) -> Result<ExitCode> {
+    validate_identity(to)?;
+    let message = message_or_stdin(message)?;
+    let node = connect(settings).await?;
+
+    let dm = DirectMessage::text(&message);
+    match send_direct(&node, to, &dm).await {
+        Ok(AckStatus::Acked) => {
+            println!("acked {}", dm.id);
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(AckStatus::LegacyAcked) => {
+            println!("acked {} (legacy peer)", dm.id);
+            Ok(ExitCode::SUCCESS)
+        }
+        Ok(AckStatus::NotAcked) => {
+            eprintln!("not acknowledged {}", dm.id);
+            Ok(ExitCode::from(EXIT_NOT_ACKED))
+        }
+        Err(e @ DeliveryError::Failed(_)) => {
+            eprintln!("{e}");
+            Ok(ExitCode::from(EXIT_UNREACHABLE))
+        }
+        Err(e @ DeliveryError::Timeout) => {
+            eprintln!("{e}");
+            Ok(ExitCode::from(EXIT_TIMEOUT))
+        }
+        Err(e @ DeliveryError::TooLarge(_)) => Err(anyhow::anyhow!("{e}")),
+    }
+}
+
+/// `publish`: send one signed message to the room.
+pub async fn publish(
+    settings: &Settings,
+    message: Option<String>,
+    settle: u64,
+) -> Result<ExitCode> {
+    let message = message_or_stdin(message)?;
+    let room = settings.room();
+    let node = connect(settings).await?;
+    let room_topic = room_topic(room);
+    node.subscribe(&room_topic).await?;
+
+    // GossipSub drops messages published before the topic mesh forms.
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(settle);
+    while !has_topic_peers(&node, &room_topic).await {
+        if tokio::time::Instant::now() >= deadline {
+            eprintln!("warning: no peers in room {room} yet; publishing anyway");
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    let group_msg = GroupMessage::text(&message, room);
+    let payload = signing::encode_signed(&Signer::from_node(&node), &group_msg);
+    check_encoded_size(&payload)?;
+    node.publish(&room_topic, payload)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to send message: {e}"))?;
+    println!("published {}", group_msg.id);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `listen`: print room messages until `count` have arrived.
+pub async fn listen(settings: &Settings, count: usize, timeout: u64) -> Result<ExitCode> {
+    let node = connect(settings).await?;
+    let room_topic = room_topic(settings.room());
+    node.subscribe(&room_topic).await?;
+    let mut pubsub_rx = node.messages().await?;
+    let my_identity = node.identity();
+
+    let receive = async {
+        let mut received = 0;
+        while received < count {
+            let Some(msg) = pubsub_rx.recv().await else {
+                break;
+            };
+            if msg.data.len() > MAX_MESSAGE_SIZE_BYTES
+                || msg.topic != room_topic
+                || msg.from == my_identity
+            {
+                continue;
+            }
+            let id_prefix = &msg.from[..8.min(msg.from.len())];
+            let (_, line) = decode_room_message(&msg.data, id_prefix, None);
+            let flag = match signing::decode_signed(&msg.data, &msg.from) {
+                Some((_, Authenticity::Verified)) => "",
+                Some((_, Authenticity::Invalid(_))) => "[bad signature] ",
+                _ => "[unsigned] ",
+            };
+            println!("{flag}{}", crate::sanitize::text(&line));
+            received += 1;
+        }
+        received
+    };
+
+    let received = if timeout == 0 {
+        receive.await
+    } else {
+        match tokio::time::timeout(Duration::from_secs(timeout), receive).await {
+            Ok(n) => n,
+            Err(_) => return Ok(ExitCode::from(EXIT_TIMEOUT)),
+        }
+    };
+
+    if received < count {
+        anyhow::bail!("Message stream closed after {received} of {count} messages");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn has_topic_peers(node: &Node, topic: &str) -> bool {
+    node.gossipsub_topic_peers()
+        .await
+        .iter()
+        .any(|tp| tp.topic == topic && !(tp.eager_peers.is_empty() && tp.lazy_peers.is_empty()))
+}
+
+/// Use the `--message` value, or read the whole of stdin when it is absent.
+fn message_or_stdin(message: Option<String>) -> Result<String> {
+    let message = match message {
+        Some(m) => m,
+        None => {
+            let mut buf = String::new();
+            std::io::stdin()
+                .read_to_string(&mut buf)
+                .context("Failed to read message from stdin")?;
+            buf.trim_end().to_string()
+        }
+    };
+    if message.is_empty() {
+        anyhow::bail!("Message is empty");
+    }
+    if message.len() > MAX_MESSAGE_SIZE_BYTES {
+        anyhow::bail!("Message too large (max {} bytes)", MAX_MESSAGE_SIZE_BYTES);
+    }
+    Ok(message)
+}
@@ -0,0 +1,386 @@
+//! Opt-in presence and typing indicators
+//!
+//! Protocol v1.3 dropped presence because its heartbeats were chatter for
+//! every peer. This extension brings it back only for peers that enable it
+//! (`presence = true` or `--presence`): presence travels on a topic of its
+//! own per room, [`topic`], which nobody else subscribes to. Publishing on
+//! it is how a peer advertises the capability, and typing notices are only
+//! sent in DMs to peers seen there. The terminal hands over whole lines, so
+//! we only know someone is typing while they compose a `/paste`.
+//!
+//! A peer announces its state when it changes and again every
+//! [`HEARTBEAT_INTERVAL`]; peers not heard from for [`EXPIRY`] are
+//! forgotten. Typing notices go out at most once per [`TYPING_INTERVAL`]
+//! and inbound presence is rate limited per peer. Private rooms have no
+//! presence, since the presence topic is not encrypted.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::sanitize;
+
+/// How often our state is re-announced.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Peers not heard from for this long are forgotten.
+const EXPIRY: Duration = Duration::from_secs(3 * 60);
+/// Least time between two of our typing notices.
+const TYPING_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a peer counts as typing after their last notice.
+const TYPING_EXPIRY: Duration = Duration::from_secs(15);
+/// Sustained presence messages per second accepted from one peer.
+pub const RATE: f64 = 0.2;
+pub const BURST: f64 = 5.0;
+/// Longest away message kept, in characters.
+pub const MAX_STATUS_CHARS: usize = 64;
+
+pub const TOPIC_PREFIX: &str = "six7-presence:";
+
+/// Presence topic of `room`. Its prefix is the longest of any room topic,
+/// so it sets how long a room name may be.
+pub fn topic(room: &str) -> String {
+    format!("{TOPIC_PREFIX}{room}")
+}
+
+/// Room whose presence topic `topic` is.
+pub fn room_of(topic: &str) -> Option<&str> {
+    topic.strip_prefix(TOPIC_PREFIX)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Online,
+    Away,
+    Offline,
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Online => write!(f, "online"),
+            State::Away => write!(f, "away"),
+            State::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Content of a `presence` message (JSON).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresencePayload {
+    pub state: State,
+    /// Away message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// The sender is composing a message (with us: a `/paste`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub typing: bool,
+}
+
+impl PresencePayload {
+    pub fn parse(content: &str) -> Result<Self> {
+        serde_json::from_str(content).context("Malformed presence")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("presence serialization is infallible")
+    }
+}
+
+/// A change in a peer's presence worth showing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notice {
+    State(State, Option<String>),
+    Typing,
+}
+
+impl std::fmt::Display for Notice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notice::State(state, Some(status)) => write!(f, "is {state}: {status}"),
+            Notice::State(state, None) => write!(f, "is {state}"),
+            Notice::Typing => write!(f, "is typing…"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PeerPresence {
+    state: State,
+    status: Option<String>,
+    seen: Instant,
+    typing_until: Option<Instant>,
+}
+
+impl PeerPresence {
+    fn typing(&self, now: Instant) -> bool {
+        self.typing_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Our presence and what we know of other peers'.
+#[derive(Debug)]
+pub struct Presence {
+    /// Whether we take part in the extension at all
+    pub enabled: bool,
+    state: State,
+    status: Option<String>,
+    last_typing: Option<Instant>,
+    /// Room to peer identity to their presence
+    rooms: HashMap<String, HashMap<String, PeerPresence>>,
+    /// DM peers typing to us, until when
+    dm_typing: HashMap<String, Instant>,
+}
+
+pub type SharedPresence = Arc<RwLock<Presence>>;
+
+/// An away message as shown to others: sanitized, on one line and short.
+fn clean_status(status: &str) -> Option<String> {
+    let status: String = sanitize::text(status)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_STATUS_CHARS)
+        .collect();
+    (!status.is_empty()).then_some(status)
+}
+
+impl Presence {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            state: State::Online,
+            status: None,
+            last_typing: None,
+            rooms: HashMap::new(),
+            dm_typing: HashMap::new(),
+        }
+    }
+
+    /// Our current state, to announce.
+    pub fn payload(&self) -> PresencePayload {
+        PresencePayload {
+            state: self.state,
+            status: self.status.clone(),
+            typing: false,
+        }
+    }
+
+    /// Set our own state. Returns `false` if nothing changed.
+    pub fn set(&mut self, state: State, status: Option<&str>) -> bool {
+        let status = status.and_then(clean_status);
+        if self.state == state && self.status == status {
+            return false;
+        }
+        self.state = state;
+        self.status = status;
+        true
+    }
+
+    /// A typing notice to send now, unless we sent one recently.
+    pub fn typing(&mut self, now: Instant) -> Option<PresencePayload> {
+        if !self.enabled
+            || self
+                .last_typing
+                .is_some_and(|last| now.duration_since(last) < TYPING_INTERVAL)
+        {
+            return None;
+        }
+        self.last_typing = Some(now);
+        Some(PresencePayload {
+            typing: true,
+            ..self.payload()
+        })
+    }
+
+    /// Record `payload` from `peer` in `room` and return what to show.
+    /// Heartbeats that repeat the known state show nothing.
+    pub fn update(
+        &mut self,
+        room: &str,
+        peer: &str,
+        payload: &PresencePayload,
+        now: Instant,
+    ) -> Option<Notice> {
+        let peers = self.rooms.entry(room.to_string()).or_default();
+        if payload.state == State::Offline {
+            return peers
+                .remove(peer)
+                .map(|_| Notice::State(State::Offline, None));
+        }
+        let status = payload.status.as_deref().and_then(clean_status);
+        let typing_until = payload.typing.then(|| now + TYPING_EXPIRY);
+        match peers.get_mut(peer) {
+            Some(known) if known.state == payload.state && known.status == status => {
+                let was_typing = known.typing(now);
+                known.seen = now;
+                known.typing_until = typing_until;
+                (payload.typing && !was_typing).then_some(Notice::Typing)
+            }
+            _ => {
+                let notice = Notice::State(payload.state, status.clone());
+                peers.insert(
+                    peer.to_string(),
+                    PeerPresence {
+                        state: payload.state,
+                        status,
+                        seen: now,
+                        typing_until,
+                    },
+                );
+                Some(notice)
+            }
+        }
+    }
+
+    /// `peer` sent a message in `room`, so they are done typing.
+    pub fn stop_typing(&mut self, room: &str, peer: &str) {
+        if let Some(known) = self.rooms.get_mut(room).and_then(|p| p.get_mut(peer)) {
+            known.typing_until = None;
+        }
+    }
+
+    /// A DM typing notice from `peer`. Returns `true` if they just started.
+    pub fn dm_typing(&mut self, peer: &str, now: Instant) -> bool {
+        let was_typing = self.dm_typing.get(peer).is_some_and(|until| *until > now);
+        self.dm_typing.insert(peer.to_string(), now + TYPING_EXPIRY);
+        !was_typing
+    }
+
+    /// `peer` sent us a DM, so they are done typing.
+    pub fn stop_dm_typing(&mut self, peer: &str) {
+        self.dm_typing.remove(peer);
+    }
+
+    /// What `peer` is up to in `room`, e.g. `away: lunch` or `typing…`.
+    pub fn describe(&self, room: &str, peer: &str, now: Instant) -> Option<String> {
+        let known = self.rooms.get(room)?.get(peer)?;
+        if known.typing(now) {
+            return Some("typing…".to_string());
+        }
+        Some(match known.status {
+            Some(ref status) => format!("{}: {status}", known.state),
+            None => known.state.to_string(),
+        })
+    }
+
+    /// Whether `peer` takes part in the extension, as far as we know.
+    pub fn supports(&self, peer: &str) -> bool {
+        self.rooms.values().any(|peers| peers.contains_key(peer))
+    }
+
+    /// Forget peers not heard from in a while, and rooms not in `joined`.
+    pub fn prune(&mut self, joined: &[String], now: Instant) {
+        self.rooms.retain(|room, _| joined.contains(room));
+        for peers in self.rooms.values_mut() {
+            peers.retain(|_, p| now.duration_since(p.seen) < EXPIRY);
+        }
+        self.dm_typing.retain(|_, until| *until > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "room";
+    const PEER: &str = "peer";
+
+    fn payload(state: State, status: Option<&str>, typing: bool) -> PresencePayload {
+        PresencePayload {
+            state,
+            status: status.map(String::from),
+            typing,
+        }
+    }
+
+    #[test]
+    fn update_cases() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let online = payload(State::Online, None, false);
+        let cases = [
+            // payload, seconds after start, expected notice
+            (online.clone(), 0, Some(Notice::State(State::Online, None))),
+            // A heartbeat repeating the state is silent
+            (online.clone(), 60, None),
+            (payload(State::Online, Some("  "), false), 61, None),
+            // A state or status change is shown
+            (
+                payload(State::Away, Some("lunch"), false),
+                62,
+                Some(Notice::State(State::Away, Some("lunch".to_string()))),
+            ),
+            (
+                payload(State::Away, Some("coffee"), false),
+                63,
+                Some(Notice::State(State::Away, Some("coffee".to_string()))),
+            ),
+            (online.clone(), 64, Some(Notice::State(State::Online, None))),
+            // Typing is shown when it starts, not while it goes on
+            (payload(State::Online, None, true), 65, Some(Notice::Typing)),
+            (payload(State::Online, None, true), 66, None),
+            (
+                payload(State::Online, None, true),
+                66 + 15,
+                Some(Notice::Typing),
+            ),
+            // Going offline forgets the peer, once
+            (
+                payload(State::Offline, None, false),
+                90,
+                Some(Notice::State(State::Offline, None)),
+            ),
+            (payload(State::Offline, None, false), 91, None),
+        ];
+        let mut presence = Presence::new(true);
+        for (payload, secs, expected) in cases {
+            let now = start + second * secs;
+            assert_eq!(
+                presence.update(ROOM, PEER, &payload, now),
+                expected,
+                "{payload:?} at {secs}s"
+            );
+        }
+        assert!(!presence.supports(PEER));
+    }
+
+    #[test]
+    fn typing_notices_are_spaced() {
+        let start = Instant::now();
+        let mut presence = Presence::new(true);
+        assert!(presence.typing(start).is_some_and(|p| p.typing));
+        assert!(presence.typing(start + TYPING_INTERVAL / 2).is_none());
+        assert!(presence.typing(start + TYPING_INTERVAL).is_some());
+        assert!(Presence::new(false).typing(start).is_none());
+    }
+
+    #[test]
+    fn prune_forgets_silent_peers_and_left_rooms() {
+        let start = Instant::now();
+        let online = payload(State::Online, None, false);
+        let mut presence = Presence::new(true);
+        presence.update(ROOM, "quiet", &online, start);
+        presence.update(ROOM, PEER, &online, start);
+        presence.update("left", "elsewhere", &online, start);
+        presence.update(ROOM, PEER, &online, start + EXPIRY / 2);
+
+        presence.prune(&[ROOM.to_string()], start + EXPIRY);
+        assert!(presence.supports(PEER));
+        assert!(!presence.supports("quiet"));
+        assert!(!presence.supports("elsewhere"));
+        assert_eq!(
+            presence.describe(ROOM, PEER, start + EXPIRY).as_deref(),
+            Some("online")
+        );
+
+        presence.prune(&[ROOM.to_string()], start + EXPIRY / 2 + EXPIRY);
+        assert!(!presence.supports(PEER));
+    }
+}
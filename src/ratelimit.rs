@@ -0,0 +1,209 @@
+//! Per-peer inbound rate limiting
+//!
+//! Each peer gets a token bucket per kind of traffic (DMs, room messages).
+//! A message costs one token; tokens refill at a steady rate up to a burst
+//! size. Peers that keep sending into an empty bucket are muted for a while
+//! so a single flooding node cannot take over the room view.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Sustained DMs per second accepted from one peer.
+pub const DM_RATE: f64 = 1.0;
+pub const DM_BURST: f64 = 10.0;
+/// Sustained room messages per second accepted from one peer.
+pub const ROOM_RATE: f64 = 2.0;
+pub const ROOM_BURST: f64 = 20.0;
+/// Consecutive throttled messages that trigger an automatic mute.
+pub const AUTO_MUTE_AFTER: u32 = 20;
+/// How long an automatic mute lasts.
+pub const AUTO_MUTE_DURATION: Duration = Duration::from_secs(300);
+/// Most peers tracked at once; refilled buckets are dropped first, then
+/// the least recently used.
+const MAX_TRACKED_PEERS: usize = 1000;
+
+/// What to do with one inbound message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Over the limit; drop it
+    Throttled,
+    /// Over the limit for too long; the peer was muted just now
+    AutoMuted,
+    /// Peer is still serving an automatic mute
+    Muted,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// Throttled messages since the last accepted one
+    overflow: u32,
+    muted_until: Option<Instant>,
+}
+
+/// Token buckets for one kind of traffic, keyed by peer identity.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Charge one message from `peer`, received at `now`, and decide
+    /// whether to accept it.
+    pub fn check(&mut self, peer: &str, now: Instant) -> Verdict {
+        if !self.buckets.contains_key(peer) && self.buckets.len() >= MAX_TRACKED_PEERS {
+            self.prune(now);
+        }
+        let (rate, burst) = (self.rate, self.burst);
+        let bucket = self
+            .buckets
+            .entry(peer.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: burst,
+                last: now,
+                overflow: 0,
+                muted_until: None,
+            });
+
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.last = now;
+
+        match bucket.muted_until {
+            Some(until) if now < until => return Verdict::Muted,
+            Some(_) => {
+                bucket.muted_until = None;
+                bucket.overflow = 0;
+            }
+            None => {}
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.overflow = 0;
+            return Verdict::Allow;
+        }
+        bucket.overflow += 1;
+        if bucket.overflow >= AUTO_MUTE_AFTER {
+            bucket.muted_until = Some(now + AUTO_MUTE_DURATION);
+            return Verdict::AutoMuted;
+        }
+        Verdict::Throttled
+    }
+
+    /// Drop buckets that have refilled completely and are not muted, then
+    /// the least recently used ones until a new peer fits.
+    fn prune(&mut self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, b| {
+            let refilled = b.tokens + now.duration_since(b.last).as_secs_f64() * rate >= burst;
+            !refilled || b.muted_until.is_some_and(|until| now < until)
+        });
+        while self.buckets.len() >= MAX_TRACKED_PEERS {
+            let Some(oldest) = self
+                .buckets
+                .iter()
+                .min_by_key(|(_, b)| b.last)
+                .map(|(peer, _)| peer.clone())
+            else {
+                break;
+            };
+            self.buckets.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "peer";
+
+    #[test]
+    fn burst_then_refill() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2.0, 3.0);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(PEER, start), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(PEER, start), Verdict::Throttled);
+        // Other peers have their own bucket
+        assert_eq!(limiter.check("other", start), Verdict::Allow);
+
+        // Half a second refills one token at two per second
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check(PEER, later), Verdict::Allow);
+        assert_eq!(limiter.check(PEER, later), Verdict::Throttled);
+
+        // A long pause refills no more than the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(PEER, much_later), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(PEER, much_later), Verdict::Throttled);
+    }
+
+    #[test]
+    fn flooding_mutes_until_expiry() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(1.0, 1.0);
+        assert_eq!(limiter.check(PEER, start), Verdict::Allow);
+        for _ in 1..AUTO_MUTE_AFTER {
+            assert_eq!(limiter.check(PEER, start), Verdict::Throttled);
+        }
+        assert_eq!(limiter.check(PEER, start), Verdict::AutoMuted);
+
+        // Refilled tokens do not lift the mute early
+        let during = start + AUTO_MUTE_DURATION - Duration::from_secs(1);
+        assert_eq!(limiter.check(PEER, during), Verdict::Muted);
+
+        // Afterwards the peer starts over with a fresh overflow count
+        let after = start + AUTO_MUTE_DURATION;
+        assert_eq!(limiter.check(PEER, after), Verdict::Allow);
+        for _ in 1..AUTO_MUTE_AFTER {
+            assert_eq!(limiter.check(PEER, after), Verdict::Throttled);
+        }
+        assert_eq!(limiter.check(PEER, after), Verdict::AutoMuted);
+    }
+
+    #[test]
+    fn accepted_message_resets_overflow() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(1.0, 1.0);
+        assert_eq!(limiter.check(PEER, start), Verdict::Allow);
+        for _ in 1..AUTO_MUTE_AFTER {
+            assert_eq!(limiter.check(PEER, start), Verdict::Throttled);
+        }
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check(PEER, later), Verdict::Allow);
+        assert_eq!(limiter.check(PEER, later), Verdict::Throttled);
+    }
+
+    #[test]
+    fn tracked_peers_stay_bounded() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(1.0, 2.0);
+        // Every bucket is partly drained, so none is pruned as refilled
+        for i in 0..MAX_TRACKED_PEERS * 2 {
+            let now = start + Duration::from_millis(i as u64);
+            assert_eq!(limiter.check(&format!("peer{i}"), now), Verdict::Allow);
+            assert!(limiter.buckets.len() <= MAX_TRACKED_PEERS);
+        }
+        // The most recent peers are the ones kept
+        let newest = format!("peer{}", MAX_TRACKED_PEERS * 2 - 1);
+        assert!(limiter.buckets.contains_key(&newest));
+        assert!(!limiter.buckets.contains_key("peer0"));
+    }
+}
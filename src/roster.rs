@@ -0,0 +1,154 @@
+//! Who is in a room
+//!
+//! Presence ([`crate::presence`]) is opt-in, so the roster relies on what
+//! every peer has: the gossipsub mesh, the peers we exchange the room's
+//! topic with. The roster polls the mesh every [`POLL_INTERVAL`] and reports peers that
+//! appear or disappear as joins and leaves. Meshes churn, so a peer only
+//! counts as gone after it has been missing for [`LEAVE_AFTER`] polls.
+//!
+//! The mesh only holds peers connected to us, so in a large room it is a
+//! subset of the members.
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+/// How often the mesh is polled.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Consecutive polls a peer must be missing before it counts as gone.
+const LEAVE_AFTER: u32 = 2;
+
+#[derive(Debug, Default)]
+struct RoomRoster {
+    present: BTreeSet<String>,
+    /// Present peers missing from the latest polls, and for how many
+    missing: HashMap<String, u32>,
+}
+
+/// Peers that came and went in one room since the previous poll.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub joined: Vec<String>,
+    pub left: Vec<String>,
+}
+
+/// Topic peers seen per room.
+#[derive(Debug, Default)]
+pub struct Roster {
+    rooms: HashMap<String, RoomRoster>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the peers currently in `room`'s mesh. The first poll of a
+    /// room only sets the baseline and reports nothing.
+    pub fn update(&mut self, room: &str, current: &BTreeSet<String>) -> Changes {
+        let Some(roster) = self.rooms.get_mut(room) else {
+            self.rooms.insert(
+                room.to_string(),
+                RoomRoster {
+                    present: current.clone(),
+                    missing: HashMap::new(),
+                },
+            );
+            return Changes::default();
+        };
+        let mut changes = Changes::default();
+        for peer in current {
+            roster.missing.remove(peer);
+            if roster.present.insert(peer.clone()) {
+                changes.joined.push(peer.clone());
+            }
+        }
+        let absent: Vec<String> = roster.present.difference(current).cloned().collect();
+        for peer in absent {
+            let polls = roster.missing.entry(peer.clone()).or_insert(0);
+            *polls += 1;
+            if *polls >= LEAVE_AFTER {
+                roster.missing.remove(&peer);
+                roster.present.remove(&peer);
+                changes.left.push(peer);
+            }
+        }
+        changes
+    }
+
+    /// Stop tracking rooms not in `joined`.
+    pub fn retain(&mut self, joined: &[String]) {
+        self.rooms.retain(|room, _| joined.contains(room));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "room";
+
+    fn peers(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn polls_report_joins_and_leaves() {
+        // Mesh at each poll, then the joins and leaves it should report
+        let cases: [(&[&str], &[&str], &[&str]); 9] = [
+            // The first poll is a silent baseline
+            (&["a", "b"], &[], &[]),
+            (&["a", "b"], &[], &[]),
+            // A join is reported once
+            (&["a", "b", "c"], &["c"], &[]),
+            (&["a", "b", "c"], &[], &[]),
+            // A leave only after LEAVE_AFTER missed polls
+            (&["a", "c"], &[], &[]),
+            (&["a", "c"], &[], &["b"]),
+            (&["a", "c"], &[], &[]),
+            // A peer that flaps back is neither a leave nor a join
+            (&["c"], &[], &[]),
+            (&["a", "c"], &[], &[]),
+        ];
+        let mut roster = Roster::new();
+        for (poll, (mesh, joined, left)) in cases.into_iter().enumerate() {
+            let changes = roster.update(ROOM, &peers(mesh));
+            assert_eq!(
+                changes.joined,
+                peers(joined).into_iter().collect::<Vec<_>>(),
+                "poll {poll}"
+            );
+            assert_eq!(
+                changes.left,
+                peers(left).into_iter().collect::<Vec<_>>(),
+                "poll {poll}"
+            );
+        }
+    }
+
+    #[test]
+    fn flapping_restarts_the_count() {
+        let mut roster = Roster::new();
+        roster.update(ROOM, &peers(&["a"]));
+        for _ in 0..3 {
+            assert!(roster.update(ROOM, &peers(&[])).left.is_empty());
+            assert!(roster.update(ROOM, &peers(&["a"])).joined.is_empty());
+        }
+        assert!(roster.update(ROOM, &peers(&[])).left.is_empty());
+        assert_eq!(roster.update(ROOM, &peers(&[])).left, ["a"]);
+        // Back after leaving, it is a join again
+        assert_eq!(roster.update(ROOM, &peers(&["a"])).joined, ["a"]);
+    }
+
+    #[test]
+    fn rooms_are_independent() {
+        let mut roster = Roster::new();
+        roster.update(ROOM, &peers(&["a"]));
+        // A room's first poll is silent even when peers are known elsewhere
+        assert!(roster
+            .update("other", &peers(&["a", "b"]))
+            .joined
+            .is_empty());
+        roster.retain(&["other".to_string()]);
+        assert!(roster.update(ROOM, &peers(&["a", "b"])).joined.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! Sanitization of text received from peers
+//!
+//! Everything a peer controls (message content, display names, room names
+//! from invites) goes through one of these functions before it reaches the
+//! terminal or our peer directory:
+//!
+//! - [`text`] for message content: drops control characters (including
+//!   ESC, so no terminal escape sequences), bidi overrides and isolates
+//!   that reorder what follows them, and invisible characters. Zero-width
+//!   joiners and variation selectors stay, as emoji sequences need them.
+//!   Lines after the first are indented, so a peer cannot start a line
+//!   that looks like one of ours (`[room] name: ...`).
+//! - [`name`] for display names: the [`text`] rules plus no joiners or
+//!   variation selectors, fullwidth letters folded to ASCII, whitespace
+//!   collapsed and `@` removed, so a name cannot pose as `name@prefix`.
+//! - [`topic`] for room names, which must survive unchanged to be valid.
+//!
+//! [`skeleton`] maps look-alike letters from other scripts to Latin ones so
+//! names can be compared for impersonation.
+
+/// Longest display name kept, in characters.
+pub const MAX_NAME_CHARS: usize = 32;
+
+/// Prefix of every line of message content after the first.
+const CONTINUATION: &str = "  ";
+
+/// Bidi embeddings, overrides, isolates and marks.
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// Characters that render as nothing (or nearly nothing).
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{115F}'
+            | '\u{1160}'
+            | '\u{17B4}'
+            | '\u{17B5}'
+            | '\u{180E}'
+            | '\u{200B}'
+            | '\u{200C}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{206A}'..='\u{206F}'
+            | '\u{3164}'
+            | '\u{FEFF}'
+            | '\u{FFA0}'
+            | '\u{FFF9}'..='\u{FFFB}'
+            | '\u{1D173}'..='\u{1D17A}'
+            | '\u{E0000}'..='\u{E007F}'
+    )
+}
+
+/// Joiners and selectors that are only meaningful inside emoji.
+fn is_emoji_glue(c: char) -> bool {
+    matches!(c, '\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
+fn is_dropped(c: char) -> bool {
+    (c.is_control() && c != '\n' && c != '\t') || is_bidi_control(c) || is_invisible(c)
+}
+
+/// Message content, safe to print, with lines after the first indented.
+/// Lines that are already indented are left alone, so sanitizing twice
+/// changes nothing.
+pub fn text(s: &str) -> String {
+    let cleaned: String = s.chars().filter(|c| !is_dropped(*c)).collect();
+    let mut out = String::with_capacity(cleaned.len());
+    for (i, line) in cleaned.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+            if !line.starts_with(CONTINUATION) {
+                out.push_str(CONTINUATION);
+            }
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+/// A display name, safe to print and to store in the peer directory.
+pub fn name(s: &str) -> String {
+    let cleaned: String = s
+        .chars()
+        .filter(|c| !is_dropped(*c) && !is_emoji_glue(*c) && *c != '@')
+        .map(fold_fullwidth)
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    cleaned
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_NAME_CHARS)
+        .collect()
+}
+
+/// A room name with everything [`text`] would drop removed, and no
+/// whitespace other than plain spaces.
+pub fn topic(s: &str) -> String {
+    s.chars()
+        .filter(|c| !is_dropped(*c) && !is_emoji_glue(*c))
+        .filter(|c| *c == ' ' || !c.is_whitespace())
+        .collect()
+}
+
+/// Fullwidth ASCII (U+FF01..U+FF5E) and the ideographic space to ASCII.
+fn fold_fullwidth(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
+
+/// Latin look-alike of a Cyrillic or Greek letter, if it has one.
+fn latin_lookalike(c: char) -> Option<char> {
+    let latin = match c {
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' | 'ϲ' => 'c',
+        'ԁ' => 'd',
+        'е' | 'ε' => 'e',
+        'һ' => 'h',
+        'і' | 'ι' | 'ӏ' => 'i',
+        'ј' => 'j',
+        'к' | 'κ' => 'k',
+        'м' => 'm',
+        'н' | 'η' => 'n',
+        'о' | 'ο' | 'σ' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'υ' => 'u',
+        'ν' => 'v',
+        'ԝ' | 'ω' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'γ' => 'y',
+        _ => return None,
+    };
+    Some(latin)
+}
+
+/// Comparison form of a name: sanitized, lowercased, with look-alike
+/// letters and digits folded together. Two names with the same skeleton
+/// look the same to a reader.
+pub fn skeleton(s: &str) -> String {
+    name(s)
+        .to_lowercase()
+        .chars()
+        .map(|c| latin_lookalike(c).unwrap_or(c))
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | 'l' | '|' => 'i',
+            '5' => 's',
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_cases() {
+        let cases = [
+            ("hello", "hello"),
+            ("two\nlines\tand tab", "two\n  lines\tand tab"),
+            ("hi\n[lobby] admin: forged", "hi\n  [lobby] admin: forged"),
+            ("line\r\n\r\nbreaks", "line\n  \n  breaks"),
+            ("already\n  indented", "already\n  indented"),
+            ("\x1b[2J\x1b[31mred", "[2J[31mred"),
+            ("bell\x07 and backspace\x08", "bell and backspace"),
+            ("carriage\rreturn", "carriagereturn"),
+            ("\u{9b}31m csi", "31m csi"),
+            ("evil\u{202E}gpj.exe", "evilgpj.exe"),
+            ("\u{2067}isolate\u{2069}", "isolate"),
+            ("mark\u{200F}\u{200E}s", "marks"),
+            ("zero\u{200B}width", "zerowidth"),
+            ("word\u{2060}joiner\u{FEFF}", "wordjoiner"),
+            ("soft\u{00AD}hyphen", "softhyphen"),
+            ("tag\u{E0041}\u{E007F}s", "tags"),
+            ("👨\u{200D}👩\u{200D}👧", "👨\u{200D}👩\u{200D}👧"),
+            ("❤\u{FE0F}", "❤\u{FE0F}"),
+            ("", ""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(text(input), expected, "text({input:?})");
+            assert_eq!(text(expected), expected, "text(text({input:?}))");
+        }
+    }
+
+    #[test]
+    fn name_cases() {
+        let cases = [
+            ("alice", "alice"),
+            ("  alice   smith ", "alice smith"),
+            ("alice\nbob", "alice bob"),
+            ("alice\x1b]0;title\x07", "alice]0;title"),
+            ("bob@deadbeef", "bobdeadbeef"),
+            ("\u{202E}ecila", "ecila"),
+            ("al\u{200B}ice", "alice"),
+            ("al\u{200D}ice", "alice"),
+            ("ali\u{FE0F}ce", "alice"),
+            ("ａｌｉｃｅ", "alice"),
+            ("alice\u{3000}smith", "alice smith"),
+            ("\u{3164}", ""),
+            ("\u{200B}\u{200B}", ""),
+            ("Ünïcödé", "Ünïcödé"),
+            (
+                "a-name-that-is-far-too-long-to-be-shown-in-full",
+                "a-name-that-is-far-too-long-to-b",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(name(input), expected, "name({input:?})");
+        }
+    }
+
+    #[test]
+    fn topic_cases() {
+        let cases = [
+            ("lobby", "lobby"),
+            ("dev ops", "dev ops"),
+            ("lob\u{200B}by", "lobby"),
+            ("lobby\u{202E}", "lobby"),
+            ("lob\nby", "lobby"),
+            ("lob\u{2028}by", "lobby"),
+            ("\x1b[31mlobby", "[31mlobby"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(topic(input), expected, "topic({input:?})");
+        }
+    }
+
+    #[test]
+    fn skeleton_cases() {
+        let same = [
+            ("alice", "аlice"), // Cyrillic a
+            ("alice", "ALICE"),
+            ("paypal", "раураl"), // Cyrillic р, а, у
+            ("bob", "b0b"),
+            ("bill", "bi11"),
+            ("alice", "al\u{200B}ice"),
+            ("alice", "ａｌｉｃｅ"),
+            ("oscar", "οsсar"), // Greek o, Cyrillic c
+        ];
+        for (a, b) in same {
+            assert_eq!(skeleton(a), skeleton(b), "{a:?} vs {b:?}");
+        }
+        let different = [("alice", "alicia"), ("bob", "rob"), ("eve", "eva")];
+        for (a, b) in different {
+            assert_ne!(skeleton(a), skeleton(b), "{a:?} vs {b:?}");
+        }
+    }
+}
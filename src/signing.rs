@@ -0,0 +1,131 @@
+//! Ed25519 signatures with the node identity
+//!
+//! Korium identities are Ed25519 public keys, so a signature made with the
+//! node's own key can be checked by anyone against the 64-hex identity the
+//! message claims to come from, independently of the relay path.
+//!
+//! Room messages carry such a signature in a trailer after the postcard
+//! encoded [`GroupMessage`]; see [`encode_signed`] and [`decode_signed`].
+
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use korium::Node;
+
+use crate::GroupMessage;
+
+/// Signs with the local node's identity key.
+#[derive(Clone)]
+pub struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+    pub fn from_node(node: &Node) -> Self {
+        Self {
+            key: SigningKey::from_bytes(&node.keypair().secret_key_bytes()),
+        }
+    }
+
+    /// A signer with a fixed key, for tests.
+    #[cfg(test)]
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self {
+            key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    /// Sign `message`, returning the hex-encoded signature.
+    pub fn sign_hex(&self, message: &[u8]) -> String {
+        hex::encode(self.key.sign(message).to_bytes())
+    }
+
+    /// Identity (hex public key) that signatures verify against.
+    pub fn identity(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+}
+
+/// Check a hex signature over `message` by `identity` (64 hex characters).
+pub fn verify_hex(identity: &str, message: &[u8], signature: &str) -> Result<()> {
+    let public: [u8; 32] = hex::decode(identity)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .context("Signer identity is not a 32-byte key")?;
+    let signature: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .context("Signature is not 64 bytes")?;
+    VerifyingKey::from_bytes(&public)
+        .context("Signer identity is not a valid Ed25519 key")?
+        .verify_strict(message, &Signature::from_bytes(&signature))
+        .context("Bad signature")
+}
+
+/// Domain separator for room message signatures.
+const MESSAGE_DOMAIN: &str = "six7-room-message-v1";
+/// Marks the signature trailer appended after a room message.
+const TRAILER_MAGIC: [u8; 4] = *b"S7SG";
+
+/// Sender signature appended to the postcard encoding of a
+/// [`GroupMessage`]. Postcard ignores trailing bytes, so clients that do
+/// not know about signatures still decode the message itself.
+#[derive(Serialize, Deserialize)]
+struct SignatureTrailer {
+    magic: [u8; 4],
+    signature: Vec<u8>,
+}
+
+/// How far a received room message can be attributed to its sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authenticity {
+    /// Signed by the identity it was published from
+    Verified,
+    /// No signature (older clients)
+    Unsigned,
+    /// Signature present but wrong; the message may have been forged or altered
+    Invalid(String),
+}
+
+fn message_bytes(msg: &GroupMessage) -> Vec<u8> {
+    postcard::to_allocvec(&(
+        MESSAGE_DOMAIN,
+        &msg.id,
+        msg.timestamp,
+        &msg.group_id,
+        &msg.message_type,
+        &msg.content,
+    ))
+    .expect("message serialization is infallible")
+}
+
+/// Wire encoding of `msg` with our signature over
+/// `(id, timestamp, group_id, message_type, content)` appended.
+pub fn encode_signed(signer: &Signer, msg: &GroupMessage) -> Vec<u8> {
+    let mut payload = postcard::to_allocvec(msg).expect("Failed to serialize message");
+    let trailer = SignatureTrailer {
+        magic: TRAILER_MAGIC,
+        signature: signer.key.sign(&message_bytes(msg)).to_bytes().to_vec(),
+    };
+    payload.extend(postcard::to_allocvec(&trailer).expect("trailer serialization is infallible"));
+    payload
+}
+
+/// Decode a room payload and check its signature against `from`, the
+/// identity it was published by. Returns `None` when `data` is not a
+/// [`GroupMessage`] at all (legacy plain text).
+pub fn decode_signed(data: &[u8], from: &str) -> Option<(GroupMessage, Authenticity)> {
+    let (msg, rest) = postcard::take_from_bytes::<GroupMessage>(data).ok()?;
+    let authenticity = match postcard::from_bytes::<SignatureTrailer>(rest) {
+        Ok(trailer) if trailer.magic == TRAILER_MAGIC => {
+            match verify_hex(from, &message_bytes(&msg), &hex::encode(trailer.signature)) {
+                Ok(()) => Authenticity::Verified,
+                Err(e) => Authenticity::Invalid(format!("{e:#}")),
+            }
+        }
+        _ if rest.is_empty() => Authenticity::Unsigned,
+        _ => Authenticity::Invalid("Malformed signature trailer".to_string()),
+    };
+    Some((msg, authenticity))
+}
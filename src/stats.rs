@@ -0,0 +1,47 @@
+//! Six7-level traffic counters
+//!
+//! Korium's `node.telemetry()` covers the fabric; these counters cover what
+//! six7 itself does with the messages it sends and receives. They are shown
+//! by `/telemetry` next to the node's own figures and exported by the
+//! metrics endpoint (see [`crate::metrics`]).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counters shared between the message handlers and `/telemetry`.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Room messages with a valid sender signature
+    pub room_signed: AtomicU64,
+    /// Room messages without a signature (older clients)
+    pub room_unsigned: AtomicU64,
+    /// Room messages whose signature did not verify
+    pub room_bad_signature: AtomicU64,
+    /// Room messages dropped by the per-peer rate limit
+    pub room_throttled: AtomicU64,
+    /// DMs rejected by the per-peer rate limit
+    pub dm_throttled: AtomicU64,
+    /// Peers muted automatically for flooding
+    pub auto_mutes: AtomicU64,
+    /// Room messages published and DMs delivered on our behalf
+    pub messages_sent: AtomicU64,
+    /// Room messages and DMs that reached us from other peers
+    pub messages_received: AtomicU64,
+    /// Received messages discarded unseen: oversized, rate limited, or
+    /// from blocked, muted or uninvited peers
+    pub messages_dropped: AtomicU64,
+    /// DMs we sent that the peer did not acknowledge
+    pub ack_failures: AtomicU64,
+}
+
+pub type SharedStats = Arc<Stats>;
+
+/// Add one to `counter`.
+pub fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Current value of `counter`.
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
@@ -0,0 +1,39 @@
+//! Terminal styling
+//!
+//! All ANSI output goes through [`paint`] so `--output plain` can turn it
+//! off in one place.
+
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use crate::config::OutputMode;
+
+pub const BOLD: &str = "1";
+pub const ITALIC: &str = "3";
+pub const UNDERLINE: &str = "4";
+pub const REVERSE: &str = "7";
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+pub const YELLOW: &str = "33";
+pub const MAGENTA: &str = "35";
+pub const CYAN: &str = "36";
+
+static OUTPUT: OnceLock<OutputMode> = OnceLock::new();
+
+/// Select the output mode. Only the first call has an effect.
+pub fn init(mode: OutputMode) {
+    let _ = OUTPUT.set(mode);
+}
+
+pub fn color_enabled() -> bool {
+    OUTPUT.get().copied().unwrap_or_default() == OutputMode::Color
+}
+
+/// Wrap `text` in the SGR sequence `sgr` when colors are enabled.
+pub fn paint(sgr: &str, text: impl Display) -> String {
+    if color_enabled() {
+        format!("\x1b[{sgr}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
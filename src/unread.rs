@@ -0,0 +1,129 @@
+//! Read markers and unread counts
+//!
+//! For every room and DM conversation we remember the id of the last
+//! message we have read; everything after it in the conversation's history
+//! (see [`crate::history`]) that someone else sent is unread. The active
+//! room counts as read whenever we act in it or leave it, DMs when we
+//! answer or open them. For DMs we also keep the last of our own messages
+//! the peer confirmed with a read receipt. Markers are persisted per
+//! profile.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::history::Entry;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Markers {
+    /// Conversation key to the id of the last message we read
+    #[serde(default)]
+    read: BTreeMap<String, String>,
+    /// DM conversation key to the id of our last message the peer read
+    #[serde(default)]
+    peer_read: BTreeMap<String, String>,
+}
+
+/// Read markers, persisted as TOML.
+#[derive(Debug, Default)]
+pub struct ReadMarkers {
+    path: PathBuf,
+    markers: Markers,
+}
+
+pub type SharedReadMarkers = Arc<RwLock<ReadMarkers>>;
+
+impl ReadMarkers {
+    pub fn load(path: &Path) -> Result<Self> {
+        let markers = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text)
+                .with_context(|| format!("Invalid read markers {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Markers::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            markers,
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let text =
+            toml::to_string_pretty(&self.markers).context("Failed to encode read markers")?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Index of the first unread entry of conversation `key`.
+    pub fn first_unread(&self, key: &str, entries: &[Entry]) -> usize {
+        self.markers
+            .read
+            .get(key)
+            .and_then(|id| entries.iter().rposition(|e| &e.id == id))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// Messages from others in `entries` that we have not read yet.
+    pub fn unread<'a>(&self, key: &str, entries: &'a [Entry], me: &str) -> Vec<&'a Entry> {
+        entries[self.first_unread(key, entries)..]
+            .iter()
+            .filter(|e| e.from != me && !e.deleted)
+            .collect()
+    }
+
+    /// Start tracking conversation `key` so it shows up in `/unread` even
+    /// before we read anything in it.
+    pub fn track(&mut self, key: &str) -> bool {
+        if self.markers.read.contains_key(key) {
+            return false;
+        }
+        self.markers.read.insert(key.to_string(), String::new());
+        true
+    }
+
+    /// Mark everything in `entries` read. Returns `false` if it already was.
+    pub fn mark_read(&mut self, key: &str, entries: &[Entry]) -> bool {
+        let Some(last) = entries.last() else {
+            return false;
+        };
+        let previous = self.markers.read.insert(key.to_string(), last.id.clone());
+        previous.as_ref() != Some(&last.id)
+    }
+
+    /// Record a read receipt: the latest of `ids` in `entries` is the last
+    /// of our messages the peer has read.
+    pub fn mark_peer_read<'a>(
+        &mut self,
+        key: &str,
+        entries: &'a [Entry],
+        ids: &[&str],
+    ) -> Option<&'a Entry> {
+        let latest = entries
+            .iter()
+            .rev()
+            .find(|e| ids.contains(&e.id.as_str()))?;
+        self.markers
+            .peer_read
+            .insert(key.to_string(), latest.id.clone());
+        Some(latest)
+    }
+
+    /// The last of our messages in DM conversation `key` the peer has read.
+    pub fn peer_read<'a>(&self, key: &str, entries: &'a [Entry]) -> Option<&'a Entry> {
+        let id = self.markers.peer_read.get(key)?;
+        entries.iter().find(|e| &e.id == id)
+    }
+
+    /// Conversation keys with a read marker.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.markers.read.keys()
+    }
+}